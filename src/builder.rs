@@ -0,0 +1,262 @@
+use std::io::{self, Result};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::tcp::{self, CongestionControl};
+use crate::{packet_loop, Device, Interface, InterfaceHandle, TunDevice};
+
+/// Sets up an [`Interface`] before it starts. Whatever isn't set keeps the default that
+/// [`Interface::new`] runs with: the TUN device `tun0`, address 192.168.0.2/24, the
+/// device's MTU, a 64K receive and 1K send buffer per connection, a 30s MSL, a 40ms ACK
+/// delay, a TTL of 64, a 1ms tick and NewReno.
+///
+/// ```no_run
+/// use std::net::Ipv4Addr;
+/// use trust::{Cubic, InterfaceBuilder};
+///
+/// let interface = InterfaceBuilder::new()
+///     .device_name("tun1")
+///     .address(Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(255, 255, 255, 0))
+///     .mtu(1400)
+///     .congestion_control(Cubic::default())
+///     .build()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct InterfaceBuilder {
+    device_name: Option<String>,
+    mtu: Option<usize>,
+    config: tcp::Config,
+}
+
+impl InterfaceBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name of the TUN device that [`InterfaceBuilder::build`] opens.
+    pub fn device_name(mut self, name: impl Into<String>) -> Self {
+        self.device_name = Some(name.into());
+        self
+    }
+
+    /// Sets the address the interface answers to, and the netmask of the subnet it is on.
+    /// Only hosts on that subnet can be connected to, as there is no router to hand the
+    /// rest to.
+    pub fn address(mut self, addr: Ipv4Addr, netmask: Ipv4Addr) -> Self {
+        self.config.addr = addr;
+        self.config.netmask = netmask;
+        self
+    }
+
+    /// Sets the largest IP packet the interface sends or receives. It can't be more than
+    /// the device carries, which is what it defaults to.
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// Sets how many received bytes each connection may buffer before the application
+    /// reads them. This is what bounds the advertised window.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.config.recv_buffer = size;
+        self
+    }
+
+    /// Sets how many written bytes each connection may queue for sending before writes
+    /// block.
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.config.send_buffer = size;
+        self
+    }
+
+    /// Sets the maximum segment lifetime. Actively closed connections are kept in
+    /// TIME-WAIT for twice this long.
+    pub fn msl(mut self, msl: Duration) -> Self {
+        self.config.msl = msl;
+        self
+    }
+
+    /// Sets how long connections may hold back the ACK for received data, in the hope of
+    /// sending it along with data of their own. It has to be less than 500ms.
+    pub fn ack_delay(mut self, delay: Duration) -> Self {
+        self.config.ack_delay = delay;
+        self
+    }
+
+    /// Sets the time to live of the packets the interface sends.
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.config.ttl = ttl;
+        self
+    }
+
+    /// Sets how often connection timers are checked, in whole milliseconds. This is also
+    /// the clock granularity that retransmission timeouts are computed with.
+    pub fn tick(mut self, tick: Duration) -> Self {
+        self.config.tick = tick;
+        self
+    }
+
+    /// Sets the congestion control algorithm connections start out with. Each one gets
+    /// its own copy of `algorithm`.
+    pub fn congestion_control<C>(mut self, algorithm: C) -> Self
+    where
+        C: CongestionControl + Clone + Sync + 'static,
+    {
+        self.config.congestion = Arc::new(move || Box::new(algorithm.clone()));
+        self
+    }
+
+    /// Opens the TUN device and starts the interface on it.
+    pub fn build(self) -> Result<Interface> {
+        self.validate()?;
+
+        let device = TunDevice::open(self.device_name.as_deref().unwrap_or("tun0"))?;
+
+        self.start(device)
+    }
+
+    /// Starts the interface on `device` instead of a TUN device.
+    pub fn build_with_device<D: Device>(self, device: D) -> Result<Interface> {
+        if self.device_name.is_some() {
+            return Err(invalid_input(
+                "a device name only applies to the TUN device that build() opens",
+            ));
+        }
+
+        self.validate()?;
+
+        self.start(device)
+    }
+
+    /// Checks the settings that don't depend on the device.
+    fn validate(&self) -> Result<()> {
+        let config = &self.config;
+
+        let mask = u32::from(config.netmask);
+
+        if mask.leading_ones() + mask.trailing_zeros() != 32 {
+            return Err(invalid_input("netmask must be contiguous"));
+        }
+
+        let host = u32::from(config.addr) & !mask;
+
+        // the all-zeros and all-ones hosts of a subnet are its network and broadcast
+        // addresses, except on point-to-point /31s and single-host /32s
+        let reserved = mask.leading_ones() < 31 && (host == 0 || host == !mask);
+
+        if config.addr.is_unspecified()
+            || config.addr.is_broadcast()
+            || config.addr.is_multicast()
+            || reserved
+        {
+            return Err(invalid_input(
+                "address must be a host address on its subnet",
+            ));
+        }
+
+        if self.mtu.is_some_and(|mtu| mtu < tcp::MIN_MTU) {
+            return Err(invalid_input(&format!(
+                "MTU must be at least {}",
+                tcp::MIN_MTU
+            )));
+        }
+
+        check_recv_buffer(config.recv_buffer)?;
+        check_send_buffer(config.send_buffer)?;
+        check_msl(config.msl)?;
+        check_ack_delay(config.ack_delay)?;
+
+        if config.ttl == 0 {
+            return Err(invalid_input("TTL must be at least 1"));
+        }
+
+        if config.tick < Duration::from_millis(1) {
+            return Err(invalid_input("tick must be at least 1ms"));
+        }
+
+        if config.tick >= config.min_rto {
+            return Err(invalid_input(
+                "tick must be shorter than the minimum retransmission timeout",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn start<D: Device>(mut self, device: D) -> Result<Interface> {
+        self.config.mtu = match self.mtu {
+            Some(mtu) if mtu > device.mtu() => {
+                return Err(invalid_input("MTU is larger than the device's"));
+            }
+            Some(mtu) => mtu,
+            None if device.mtu() < tcp::MIN_MTU => {
+                return Err(invalid_input(&format!(
+                    "device MTU must be at least {}",
+                    tcp::MIN_MTU
+                )));
+            }
+            None => device.mtu(),
+        };
+
+        let ih: Arc<InterfaceHandle> = Default::default();
+
+        ih.manager.lock().unwrap().config = self.config;
+
+        let loop_handler = {
+            let ih = ih.clone();
+            thread::spawn(move || packet_loop(device, ih))
+        };
+
+        Ok(Interface {
+            ih: Some(ih),
+            jh: Some(loop_handler),
+        })
+    }
+}
+
+// the settings an `Interface` can still change after it has started
+
+pub(crate) fn check_recv_buffer(size: usize) -> Result<()> {
+    if size == 0 {
+        return Err(invalid_input("receive buffer must be at least 1 byte"));
+    }
+
+    if size > tcp::MAX_RECV_BUFFER {
+        return Err(invalid_input(
+            "receive buffer is larger than any window TCP can offer",
+        ));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_send_buffer(size: usize) -> Result<()> {
+    if size == 0 {
+        return Err(invalid_input("send buffer must be at least 1 byte"));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_msl(msl: Duration) -> Result<()> {
+    if msl.is_zero() {
+        return Err(invalid_input("cannot set a 0 duration MSL"));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_ack_delay(delay: Duration) -> Result<()> {
+    if delay >= tcp::MAX_ACK_DELAY {
+        return Err(invalid_input("ACK delay must be less than 500ms"));
+    }
+
+    Ok(())
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
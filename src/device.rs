@@ -0,0 +1,46 @@
+use std::io::Result;
+use std::os::fd::BorrowedFd;
+
+use crate::tcp::PacketSink;
+
+mod channel;
+mod link;
+mod pcap;
+mod tap;
+mod tun;
+
+pub use channel::ChannelDevice;
+pub use link::{LinkConditions, LinkStats, SimulatedLink};
+pub use pcap::PcapDevice;
+pub use tap::TapDevice;
+pub use tun::TunDevice;
+
+/// MTU of an Ethernet link, and what TUN and TAP devices start out with.
+const ETHERNET_MTU: usize = 1500;
+
+/// A network device an [`Interface`](crate::Interface) exchanges IPv4 packets through.
+///
+/// Devices that frame packets on the wire, like [`TapDevice`], deal with that themselves;
+/// the interface only ever sees bare IPv4 packets.
+pub trait Device: Send + 'static {
+    /// Receives one packet into `buf`, returning its length. Only called once
+    /// [`Device::poll_fd`] is readable; `Ok(0)` means nothing came in for the interface,
+    /// e.g. because the device handled what it got by itself.
+    fn recv(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Sends one packet. Devices that can't take it right now may drop it, like a full
+    /// queue on a real link would.
+    fn send(&mut self, packet: &[u8]) -> Result<()>;
+
+    /// A descriptor that polls readable while there is something to receive.
+    fn poll_fd(&self) -> BorrowedFd<'_>;
+
+    /// Largest packet the device carries, not counting any framing.
+    fn mtu(&self) -> usize;
+}
+
+impl<D: Device> PacketSink for D {
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        Device::send(self, packet)
+    }
+}
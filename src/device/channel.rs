@@ -0,0 +1,81 @@
+use std::io::{self, Result};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::net::UnixDatagram;
+
+use super::{Device, ETHERNET_MTU};
+
+/// One end of an in-process link: whatever is sent on one end of a pair is received on
+/// the other. Needs no privileges, so two [`Interface`](crate::Interface)s can talk to
+/// each other inside a test.
+pub struct ChannelDevice {
+    socket: UnixDatagram,
+    mtu: usize,
+}
+
+impl ChannelDevice {
+    /// Creates two devices connected to each other.
+    pub fn pair() -> Result<(ChannelDevice, ChannelDevice)> {
+        let (a, b) = UnixDatagram::pair()?;
+
+        a.set_nonblocking(true)?;
+        b.set_nonblocking(true)?;
+
+        Ok((
+            ChannelDevice {
+                socket: a,
+                mtu: ETHERNET_MTU,
+            },
+            ChannelDevice {
+                socket: b,
+                mtu: ETHERNET_MTU,
+            },
+        ))
+    }
+
+    /// Sets the MTU this end reports (1500 by default).
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.mtu = mtu;
+    }
+
+    pub(crate) fn into_socket(self) -> UnixDatagram {
+        self.socket
+    }
+}
+
+impl Device for ChannelDevice {
+    fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.socket.recv(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            r => r,
+        }
+    }
+
+    /// Packets are dropped while the other end has too many of them queued, or once it
+    /// is gone, as if the cable had been pulled.
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        match self.socket.send(packet) {
+            // the socket refuses the first send once the other end is gone, and reports
+            // later ones as not connected
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::ConnectionRefused
+                        | io::ErrorKind::NotConnected
+                        | io::ErrorKind::BrokenPipe
+                ) =>
+            {
+                Ok(())
+            }
+            r => r.map(|_| ()),
+        }
+    }
+
+    fn poll_fd(&self) -> BorrowedFd<'_> {
+        self.socket.as_fd()
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+}
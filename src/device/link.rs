@@ -0,0 +1,258 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::Result;
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::poll::{PollFd, PollFlags, PollTimeout};
+
+use super::ChannelDevice;
+
+/// How a [`SimulatedLink`] mistreats the packets crossing it, in both directions.
+/// Probabilities are per packet; the defaults leave the link perfect.
+#[derive(Clone, Debug, Default)]
+pub struct LinkConditions {
+    /// Chance that a packet is lost.
+    pub loss: f64,
+    /// Chance that a packet arrives twice.
+    pub duplicate: f64,
+    /// Chance that a packet is held back for `reorder_delay` on top of `delay`, letting
+    /// the ones behind it overtake.
+    pub reorder: f64,
+    pub reorder_delay: Duration,
+    /// Chance that a bit of the packet gets flipped.
+    pub corrupt: f64,
+    /// One-way propagation delay.
+    pub delay: Duration,
+    /// Bandwidth in bytes per second, `None` for unlimited. Packets queue up behind each
+    /// other while the link is busy.
+    pub rate: Option<u64>,
+    /// Seed for the random choices above; the same traffic meets the same fate with the
+    /// same seed.
+    pub seed: u64,
+}
+
+/// What a [`SimulatedLink`] has done to the packets so far.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinkStats {
+    pub delivered: u64,
+    pub lost: u64,
+    /// Packets that made it across but found no room in the receiving end's queue.
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub corrupted: u64,
+}
+
+/// A link between two [`ChannelDevice`]s that drops, duplicates, reorders, corrupts,
+/// delays and rate-limits what goes over it. Put an [`Interface`](crate::Interface) on
+/// each end to stress the stack without a network. The link keeps running until dropped.
+pub struct SimulatedLink {
+    stats: Arc<Mutex<LinkStats>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl SimulatedLink {
+    /// Starts a link under `conditions` and returns it with the devices on either end.
+    pub fn new(
+        conditions: LinkConditions,
+    ) -> Result<(SimulatedLink, ChannelDevice, ChannelDevice)> {
+        let (a, near) = ChannelDevice::pair()?;
+        let (b, far) = ChannelDevice::pair()?;
+
+        let stats: Arc<Mutex<LinkStats>> = Default::default();
+        let stop: Arc<AtomicBool> = Default::default();
+
+        let mut sim = Simulator {
+            ends: [near.into_socket(), far.into_socket()],
+            rng: Rng::new(conditions.seed),
+            conditions,
+            queue: BinaryHeap::new(),
+            busy_until: [Instant::now(); 2],
+            serial: 0,
+            stats: stats.clone(),
+        };
+
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || sim.run(&stop))
+        };
+
+        let link = SimulatedLink {
+            stats,
+            stop,
+            thread: Some(thread),
+        };
+
+        Ok((link, a, b))
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+impl Drop for SimulatedLink {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Longest the simulator sleeps before checking whether it should stop.
+const IDLE_POLL: Duration = Duration::from_millis(10);
+
+/// A packet on the wire: when it comes out, a serial number to keep packets due at the
+/// same time in order, which end it comes out of, and the packet.
+type InTransit = (Instant, u64, usize, Vec<u8>);
+
+struct Simulator {
+    ends: [UnixDatagram; 2],
+    conditions: LinkConditions,
+    rng: Rng,
+    queue: BinaryHeap<Reverse<InTransit>>,
+    /// Until when each direction is busy putting earlier packets on the wire.
+    busy_until: [Instant; 2],
+    serial: u64,
+    stats: Arc<Mutex<LinkStats>>,
+}
+
+impl Simulator {
+    fn run(&mut self, stop: &AtomicBool) {
+        let mut buf = vec![0u8; 64 * 1024];
+
+        while !stop.load(Ordering::Relaxed) {
+            let wait = self
+                .queue
+                .peek()
+                .map(|Reverse((at, ..))| at.saturating_duration_since(Instant::now()))
+                .map_or(IDLE_POLL, |wait| wait.min(IDLE_POLL));
+
+            // round up, so we don't spin until a packet is due
+            let wait_ms = wait.as_micros().div_ceil(1000) as u16;
+
+            let mut fds = [
+                PollFd::new(self.ends[0].as_fd(), PollFlags::POLLIN),
+                PollFd::new(self.ends[1].as_fd(), PollFlags::POLLIN),
+            ];
+
+            if nix::poll::poll(&mut fds, PollTimeout::from(wait_ms)).is_err() {
+                return;
+            }
+
+            for from in 0..2 {
+                while let Ok(n) = self.ends[from].recv(&mut buf) {
+                    self.transmit(1 - from, buf[..n].to_vec());
+                }
+            }
+
+            let now = Instant::now();
+
+            while self
+                .queue
+                .peek()
+                .is_some_and(|Reverse((at, ..))| *at <= now)
+            {
+                let Reverse((_, _, to, packet)) = self.queue.pop().unwrap();
+
+                let mut stats = self.stats.lock().unwrap();
+
+                // a full queue on the other end loses the packet, like a real link would
+                match self.ends[to].send(&packet) {
+                    Ok(_) => stats.delivered += 1,
+                    Err(_) => stats.dropped += 1,
+                }
+            }
+        }
+    }
+
+    /// Puts `packet` on the wire towards end `to`, subject to the link's conditions.
+    fn transmit(&mut self, to: usize, mut packet: Vec<u8>) {
+        let mut stats = self.stats.lock().unwrap();
+
+        if self.rng.chance(self.conditions.loss) {
+            stats.lost += 1;
+            return;
+        }
+
+        if self.rng.chance(self.conditions.corrupt) && !packet.is_empty() {
+            let bit = self.rng.below(packet.len() as u64 * 8) as usize;
+
+            packet[bit / 8] ^= 1 << (bit % 8);
+
+            stats.corrupted += 1;
+        }
+
+        let now = Instant::now();
+
+        let mut at = now.max(self.busy_until[to]);
+
+        if let Some(rate) = self.conditions.rate {
+            at += Duration::from_secs_f64(packet.len() as f64 / rate as f64);
+        }
+
+        self.busy_until[to] = at;
+
+        at += self.conditions.delay;
+
+        if self.rng.chance(self.conditions.reorder) {
+            at += self.conditions.reorder_delay;
+
+            stats.reordered += 1;
+        }
+
+        if self.rng.chance(self.conditions.duplicate) {
+            self.queue
+                .push(Reverse((at, self.serial, to, packet.clone())));
+
+            self.serial += 1;
+
+            stats.duplicated += 1;
+        }
+
+        self.queue.push(Reverse((at, self.serial, to, packet)));
+
+        self.serial += 1;
+    }
+}
+
+/// xorshift64*, seeded through splitmix64 so that small seeds work as well as any.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        // the state must never be zero
+        Rng(z | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// True with probability `p`.
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && ((self.next() >> 11) as f64) < p * (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,328 @@
+use std::fs;
+use std::io::{self, Result};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use etherparse::{EtherType, Ethernet2Header};
+
+use super::{Device, ETHERNET_MTU};
+
+/// Link types of the captures we can replay (see the tcpdump.org list of link-layer
+/// header types).
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_IPV4: u32 = 228;
+
+const GLOBAL_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+
+/// Replays the IPv4 packets of a pcap file as if they were arriving on a link, spaced out
+/// like they were captured. What the interface sends in return is dropped.
+pub struct PcapDevice {
+    socket: UnixDatagram,
+    /// The end the replay thread writes to; kept here so ours doesn't hang up once the
+    /// capture runs out.
+    _feeder: UnixDatagram,
+}
+
+impl PcapDevice {
+    /// Reads the capture at `path` and starts replaying it. Captures of Ethernet frames
+    /// and of raw IPv4 packets are understood; frames that don't carry IPv4 are skipped.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let packets = parse(&fs::read(path)?)?;
+
+        let (socket, feeder) = UnixDatagram::pair()?;
+
+        socket.set_nonblocking(true)?;
+
+        let writer = feeder.try_clone()?;
+
+        thread::spawn(move || {
+            let start = Instant::now();
+
+            for (at, packet) in packets {
+                if let Some(wait) = at.checked_sub(start.elapsed()) {
+                    thread::sleep(wait);
+                }
+
+                if writer.send(&packet).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(PcapDevice {
+            socket,
+            _feeder: feeder,
+        })
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Picks the IPv4 packets out of a capture, along with when each was captured relative
+/// to the first one.
+fn parse(file: &[u8]) -> Result<Vec<(Duration, Vec<u8>)>> {
+    let header = file
+        .get(..GLOBAL_HEADER_LEN)
+        .ok_or_else(|| invalid("truncated pcap header"))?;
+
+    let magic = [header[0], header[1], header[2], header[3]];
+
+    let (big_endian, nanos) = match magic {
+        [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+        [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+        _ => return Err(invalid("not a pcap file")),
+    };
+
+    let u32_at = |bytes: &[u8], at: usize| {
+        let field = [bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]];
+
+        if big_endian {
+            u32::from_be_bytes(field)
+        } else {
+            u32::from_le_bytes(field)
+        }
+    };
+
+    let linktype = u32_at(header, 20) & 0xffff;
+
+    if ![LINKTYPE_ETHERNET, LINKTYPE_RAW, LINKTYPE_IPV4].contains(&linktype) {
+        return Err(invalid("unsupported pcap link type"));
+    }
+
+    let mut packets = Vec::new();
+    let mut first = None;
+    let mut rest = &file[GLOBAL_HEADER_LEN..];
+
+    while !rest.is_empty() {
+        let record = rest
+            .get(..RECORD_HEADER_LEN)
+            .ok_or_else(|| invalid("truncated pcap record"))?;
+
+        let secs = u32_at(record, 0) as u64;
+        let frac = u32_at(record, 4);
+        let len = u32_at(record, 8) as usize;
+
+        let data = rest
+            .get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len)
+            .ok_or_else(|| invalid("truncated pcap record"))?;
+
+        rest = &rest[RECORD_HEADER_LEN + len..];
+
+        let at = if nanos {
+            Duration::new(secs, frac)
+        } else {
+            Duration::new(secs, frac.saturating_mul(1000))
+        };
+
+        let first = *first.get_or_insert(at);
+
+        let packet = if linktype == LINKTYPE_ETHERNET {
+            match Ethernet2Header::from_slice(data) {
+                Ok((header, payload)) if header.ether_type == EtherType::IPV4 => payload,
+                _ => continue,
+            }
+        } else {
+            data
+        };
+
+        packets.push((at.saturating_sub(first), packet.to_vec()));
+    }
+
+    Ok(packets)
+}
+
+impl Device for PcapDevice {
+    fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.socket.recv(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            r => r,
+        }
+    }
+
+    fn send(&mut self, _packet: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll_fd(&self) -> BorrowedFd<'_> {
+        self.socket.as_fd()
+    }
+
+    fn mtu(&self) -> usize {
+        ETHERNET_MTU
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little-endian, microsecond capture of raw IP with a single 4-byte packet, as
+    /// tcpdump writes it.
+    const RAW_CAPTURE: [u8; 44] = [
+        0xd4, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, // magic, version 2.4
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // time zone, accuracy
+        0xff, 0xff, 0x00, 0x00, 0x65, 0x00, 0x00, 0x00, // snaplen, LINKTYPE_RAW
+        0x0a, 0x00, 0x00, 0x00, 0x20, 0xa1, 0x07, 0x00, // 10.5s
+        0x04, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, // captured and original length
+        0x45, 0x00, 0x00, 0x04,
+    ];
+
+    /// A capture of `records`, each `(seconds, fraction, data)`.
+    fn capture(
+        big_endian: bool,
+        nanos: bool,
+        linktype: u32,
+        records: &[(u32, u32, &[u8])],
+    ) -> Vec<u8> {
+        let u32_bytes = |n: u32| {
+            if big_endian {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            }
+        };
+
+        let magic: u32 = if nanos { 0xa1b2_3c4d } else { 0xa1b2_c3d4 };
+
+        let mut file = Vec::new();
+
+        file.extend(u32_bytes(magic));
+        file.extend(if big_endian {
+            [0, 2, 0, 4]
+        } else {
+            [2, 0, 4, 0]
+        });
+        file.extend([0; 8]);
+        file.extend(u32_bytes(0xffff));
+        file.extend(u32_bytes(linktype));
+
+        for (secs, frac, data) in records {
+            file.extend(u32_bytes(*secs));
+            file.extend(u32_bytes(*frac));
+            file.extend(u32_bytes(data.len() as u32));
+            file.extend(u32_bytes(data.len() as u32));
+            file.extend_from_slice(data);
+        }
+
+        file
+    }
+
+    fn ethernet(ether_type: EtherType, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+
+        Ethernet2Header {
+            source: [2, 0, 0, 0, 0, 1],
+            destination: [2, 0, 0, 0, 0, 2],
+            ether_type,
+        }
+        .write(&mut frame)
+        .unwrap();
+
+        frame.extend_from_slice(payload);
+
+        frame
+    }
+
+    #[test]
+    fn tcpdump_capture() {
+        assert_eq!(
+            parse(&RAW_CAPTURE).unwrap(),
+            [(Duration::ZERO, vec![0x45, 0x00, 0x00, 0x04])]
+        );
+    }
+
+    #[test]
+    fn byte_orders_and_timestamp_resolutions() {
+        // (big endian, nanoseconds, fraction for half a second)
+        for (big_endian, nanos, half) in [
+            (false, false, 500_000),
+            (true, false, 500_000),
+            (false, true, 500_000_000),
+            (true, true, 500_000_000),
+        ] {
+            let file = capture(
+                big_endian,
+                nanos,
+                LINKTYPE_RAW,
+                &[(100, 0, b"one"), (100, half, b"two"), (102, half, b"three")],
+            );
+
+            assert_eq!(
+                parse(&file).unwrap(),
+                [
+                    (Duration::ZERO, b"one".to_vec()),
+                    (Duration::from_millis(500), b"two".to_vec()),
+                    (Duration::from_millis(2500), b"three".to_vec()),
+                ],
+                "big endian {big_endian}, nanoseconds {nanos}"
+            );
+        }
+    }
+
+    #[test]
+    fn link_types() {
+        let ip = [0x45, 0x00, 0x00, 0x14];
+
+        for linktype in [LINKTYPE_RAW, LINKTYPE_IPV4] {
+            let file = capture(false, false, linktype, &[(0, 0, &ip)]);
+
+            assert_eq!(parse(&file).unwrap(), [(Duration::ZERO, ip.to_vec())]);
+        }
+
+        // Ethernet headers come off, and frames that don't carry IPv4 are skipped
+        let file = capture(
+            false,
+            false,
+            LINKTYPE_ETHERNET,
+            &[
+                (0, 0, &ethernet(EtherType::ARP, &[1; 28])),
+                (0, 1, &ethernet(EtherType::IPV4, &ip)),
+                (0, 2, &ethernet(EtherType::IPV6, &[0x60; 40])),
+                (0, 3, &[0; 6]),
+            ],
+        );
+
+        // timed from the first record all the same
+        assert_eq!(
+            parse(&file).unwrap(),
+            [(Duration::from_micros(1), ip.to_vec())]
+        );
+
+        let file = capture(false, false, 105, &[(0, 0, &ip)]);
+
+        assert_eq!(parse(&file).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_captures_are_rejected() {
+        let mut files = vec![
+            RAW_CAPTURE[..10].to_vec(),
+            // a record header cut short
+            RAW_CAPTURE[..30].to_vec(),
+            // a packet cut short
+            RAW_CAPTURE[..42].to_vec(),
+        ];
+
+        // a length that runs far past the end
+        let mut huge = RAW_CAPTURE.to_vec();
+        huge[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        files.push(huge);
+
+        let mut garbage = RAW_CAPTURE.to_vec();
+        garbage[0] = 0;
+        files.push(garbage);
+
+        for file in files {
+            assert_eq!(parse(&file).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::io::Result;
+use std::net::Ipv4Addr;
+use std::os::fd::{AsRawFd, BorrowedFd};
+
+use etherparse::{EtherType, Ethernet2Header};
+use tun_tap::{Iface, Mode};
+
+use super::{Device, ETHERNET_MTU};
+
+/// Length of an ARP packet for IPv4 over Ethernet (RFC 826).
+const ARP_LEN: usize = 28;
+
+/// Hardware type, protocol type and address lengths of ARP for IPv4 over Ethernet.
+const ARP_PREAMBLE: [u8; 6] = [0, 1, 0x08, 0x00, 6, 4];
+
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+
+const BROADCAST: [u8; 6] = [0xff; 6];
+
+/// A Linux TAP device, which deals in Ethernet frames. Packets are wrapped in and
+/// unwrapped from Ethernet II headers, and ARP is answered for the device's address and
+/// used to find the peers on the link. Opening one needs `CAP_NET_ADMIN`.
+pub struct TapDevice {
+    iface: Iface,
+    addr: Ipv4Addr,
+    mac: [u8; 6],
+    /// Hardware addresses learned from ARP and from incoming packets.
+    neighbors: HashMap<Ipv4Addr, [u8; 6]>,
+    frame: Vec<u8>,
+}
+
+impl TapDevice {
+    /// Opens the TAP device `name`, creating it if it doesn't exist yet, and answers ARP
+    /// requests for `addr` on it.
+    pub fn open(name: &str, addr: Ipv4Addr) -> Result<Self> {
+        let [a, b, c, d] = addr.octets();
+
+        Ok(TapDevice {
+            iface: Iface::without_packet_info(name, Mode::Tap)?,
+            addr,
+            // locally administered, unicast
+            mac: [0x02, 0x00, a, b, c, d],
+            neighbors: HashMap::new(),
+            frame: vec![0; Ethernet2Header::LEN + ETHERNET_MTU],
+        })
+    }
+
+    /// The name the kernel gave the device.
+    pub fn name(&self) -> &str {
+        self.iface.name()
+    }
+
+    /// The hardware address frames are sent from.
+    pub fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    fn send_frame(
+        &mut self,
+        destination: [u8; 6],
+        ether_type: EtherType,
+        payload: &[u8],
+    ) -> Result<()> {
+        let header = Ethernet2Header {
+            source: self.mac,
+            destination,
+            ether_type,
+        };
+
+        let mut frame = Vec::with_capacity(Ethernet2Header::LEN + payload.len());
+
+        frame.extend_from_slice(&header.to_bytes());
+        frame.extend_from_slice(payload);
+
+        self.iface.send(&frame).map(|_| ())
+    }
+
+    fn send_arp(&mut self, oper: u16, tha: [u8; 6], tpa: Ipv4Addr) -> Result<()> {
+        let mut arp = Vec::with_capacity(ARP_LEN);
+
+        arp.extend_from_slice(&ARP_PREAMBLE);
+        arp.extend_from_slice(&oper.to_be_bytes());
+        arp.extend_from_slice(&self.mac);
+        arp.extend_from_slice(&self.addr.octets());
+        arp.extend_from_slice(&tha);
+        arp.extend_from_slice(&tpa.octets());
+
+        let destination = if oper == ARP_REQUEST { BROADCAST } else { tha };
+
+        self.send_frame(destination, EtherType::ARP, &arp)
+    }
+
+    fn on_arp(&mut self, arp: &[u8; ARP_LEN]) -> Result<()> {
+        if arp[..6] != ARP_PREAMBLE {
+            return Ok(());
+        }
+
+        let oper = u16::from_be_bytes([arp[6], arp[7]]);
+        let sha: [u8; 6] = arp[8..14].try_into().unwrap();
+        let spa = Ipv4Addr::new(arp[14], arp[15], arp[16], arp[17]);
+        let tpa = Ipv4Addr::new(arp[24], arp[25], arp[26], arp[27]);
+
+        self.neighbors.insert(spa, sha);
+
+        if oper == ARP_REQUEST && tpa == self.addr {
+            self.send_arp(ARP_REPLY, sha, spa)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Device for TapDevice {
+    fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.iface.recv(&mut self.frame)?;
+
+        let Ok((header, payload)) = Ethernet2Header::from_slice(&self.frame[..n]) else {
+            return Ok(0);
+        };
+
+        match header.ether_type {
+            EtherType::ARP if payload.len() >= ARP_LEN => {
+                let arp = payload[..ARP_LEN].try_into().unwrap();
+
+                self.on_arp(&arp)?;
+
+                Ok(0)
+            }
+
+            EtherType::IPV4 if payload.len() >= 20 => {
+                let source = Ipv4Addr::new(payload[12], payload[13], payload[14], payload[15]);
+
+                self.neighbors.insert(source, header.source);
+
+                let len = payload.len().min(buf.len());
+
+                buf[..len].copy_from_slice(&payload[..len]);
+
+                Ok(len)
+            }
+
+            _ => Ok(0),
+        }
+    }
+
+    /// Packets to peers whose hardware address isn't known yet are dropped, after asking
+    /// for it with an ARP request; the retransmission then finds it.
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        if packet.len() < 20 {
+            return Ok(());
+        }
+
+        let destination = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+
+        match self.neighbors.get(&destination) {
+            Some(&mac) => self.send_frame(mac, EtherType::IPV4, packet),
+            None => self.send_arp(ARP_REQUEST, [0; 6], destination),
+        }
+    }
+
+    fn poll_fd(&self) -> BorrowedFd<'_> {
+        unsafe { BorrowedFd::borrow_raw(self.iface.as_raw_fd()) }
+    }
+
+    fn mtu(&self) -> usize {
+        ETHERNET_MTU
+    }
+}
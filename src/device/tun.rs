@@ -0,0 +1,44 @@
+use std::io::Result;
+use std::os::fd::{AsRawFd, BorrowedFd};
+
+use tun_tap::{Iface, Mode};
+
+use super::{Device, ETHERNET_MTU};
+
+/// A Linux TUN device, which hands over IP packets as they are. Opening one needs
+/// `CAP_NET_ADMIN`.
+pub struct TunDevice {
+    iface: Iface,
+}
+
+impl TunDevice {
+    /// Opens the TUN device `name`, creating it if it doesn't exist yet.
+    pub fn open(name: &str) -> Result<Self> {
+        Ok(TunDevice {
+            iface: Iface::without_packet_info(name, Mode::Tun)?,
+        })
+    }
+
+    /// The name the kernel gave the device.
+    pub fn name(&self) -> &str {
+        self.iface.name()
+    }
+}
+
+impl Device for TunDevice {
+    fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.iface.recv(buf)
+    }
+
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        self.iface.send(packet).map(|_| ())
+    }
+
+    fn poll_fd(&self) -> BorrowedFd<'_> {
+        unsafe { BorrowedFd::borrow_raw(self.iface.as_raw_fd()) }
+    }
+
+    fn mtu(&self) -> usize {
+        ETHERNET_MTU
+    }
+}
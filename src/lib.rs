@@ -1,21 +1,31 @@
+mod builder;
+mod clock;
+mod device;
 mod tcp;
 use etherparse::{ip_number::TCP, Ipv4HeaderSlice, TcpHeaderSlice};
 use nix::poll::PollTimeout;
-use std::cmp::min;
 use std::collections::{HashMap, VecDeque};
 use std::io;
 use std::io::Result;
 use std::net::Shutdown;
-use std::os::fd::{AsRawFd, BorrowedFd};
-use std::sync::{Arc, Condvar, Mutex};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use std::{
     io::{Read, Write},
     net::Ipv4Addr,
     thread,
 };
-use tun_tap::Iface;
 
-const SENDQUEUE_SIZE: usize = 1024;
+pub use builder::InterfaceBuilder;
+pub use clock::{Clock, ManualClock, MonotonicClock};
+pub use device::{
+    ChannelDevice, Device, LinkConditions, LinkStats, PcapDevice, SimulatedLink, TapDevice,
+    TunDevice,
+};
+pub use tcp::{CongestionControl, Cubic, FixedIss, IssGenerator, NewReno, Reno, Rfc6528Iss};
+
+/// IANA suggested range for dynamic/private ports (RFC 6335).
+const EPHEMERAL_PORTS: std::ops::RangeInclusive<u16> = 49152..=65535;
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
 struct Quad {
@@ -28,13 +38,98 @@ struct InterfaceHandle {
     manager: Mutex<ConnectionManager>,
     pending_var: Condvar,
     recv_var: Condvar,
+    send_var: Condvar,
+    connect_var: Condvar,
 }
 
 #[derive(Default)]
 struct ConnectionManager {
     terminate: bool,
     connections: HashMap<Quad, tcp::Connection>,
-    pending: HashMap<u16, VecDeque<Quad>>,
+    listeners: HashMap<u16, Listener>,
+    next_port: u16,
+    config: tcp::Config,
+}
+
+/// A bound port: the connections waiting to be accepted, and the options of its
+/// `TcpListener`.
+#[derive(Default)]
+struct Listener {
+    pending: VecDeque<Quad>,
+    nonblocking: bool,
+    read_timeout: Option<Duration>,
+    /// Handed down to the streams accepted from here.
+    write_timeout: Option<Duration>,
+}
+
+impl ConnectionManager {
+    fn ephemeral_port(&mut self) -> Option<u16> {
+        let first = *EPHEMERAL_PORTS.start();
+
+        let count = EPHEMERAL_PORTS.len() as u16;
+
+        for _ in 0..count {
+            let port = first + self.next_port % count;
+
+            self.next_port = self.next_port.wrapping_add(1);
+
+            let in_use = self.listeners.contains_key(&port)
+                || self.connections.keys().any(|q| q.dest.1 == port);
+
+            if !in_use {
+                return Some(port);
+            }
+        }
+
+        None
+    }
+}
+
+/// Rejects a zero timeout, like the `std::net` setters do.
+fn check_timeout(timeout: Option<Duration>) -> Result<()> {
+    if timeout == Some(Duration::ZERO) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot set a 0 duration timeout",
+        ));
+    }
+
+    Ok(())
+}
+
+fn deadline_after(timeout: Option<Duration>) -> Option<Instant> {
+    timeout.map(|timeout| Instant::now() + timeout)
+}
+
+/// Waits on `var` until notified. Fails with `WouldBlock` right away if `nonblocking`,
+/// and with `TimedOut` once `deadline` has passed.
+fn wait<'a>(
+    var: &Condvar,
+    cm: MutexGuard<'a, ConnectionManager>,
+    nonblocking: bool,
+    deadline: Option<Instant>,
+) -> Result<MutexGuard<'a, ConnectionManager>> {
+    if nonblocking {
+        return Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "Operation would block",
+        ));
+    }
+
+    let Some(deadline) = deadline else {
+        return Ok(var.wait(cm).unwrap());
+    };
+
+    let now = Instant::now();
+
+    if now >= deadline {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "Operation timed out",
+        ));
+    }
+
+    Ok(var.wait_timeout(cm, deadline - now).unwrap().0)
 }
 
 pub struct Interface {
@@ -57,27 +152,66 @@ impl Drop for Interface {
     }
 }
 
-fn packet_loop(mut nic: Iface, ih: Arc<InterfaceHandle>) -> Result<()> {
-    let mut buf = [0u8; 1504];
+fn packet_loop<D: Device>(mut nic: D, ih: Arc<InterfaceHandle>) -> Result<()> {
+    let mut buf = vec![0u8; nic.mtu()];
+
+    let (addr, tick) = {
+        let cm = ih.manager.lock().unwrap();
+
+        (cm.config.addr, cm.config.tick)
+    };
+
+    let timeout = PollTimeout::try_from(tick).expect("tick too long to poll for");
+
+    let mut last_tick: Option<Instant> = None;
 
     loop {
-        let borrowed_fd = unsafe { BorrowedFd::borrow_raw(nic.as_raw_fd()) };
+        {
+            let mut cmg = ih.manager.lock().unwrap();
 
-        use nix::poll::PollFlags;
+            if cmg.terminate {
+                return Ok(());
+            }
 
-        let pfd = nix::poll::PollFd::new(borrowed_fd, PollFlags::POLLIN);
+            let now = cmg.config.clock.now();
 
-        let n = nix::poll::poll(&mut [pfd], PollTimeout::from(1u8))?;
+            // tick on time even while packets keep coming in, or a busy link would hold up
+            // every timer; a clock that was swapped for one behind it ticks right away
+            let due = last_tick.is_none_or(|t| !(t..t + tick).contains(&now));
 
-        assert_ne!(n, -1);
+            if due {
+                last_tick = Some(now);
 
-        if n == 0 {
-            let mut cmg = ih.manager.lock().unwrap();
+                let mut any_closed = false;
+
+                for connection in cmg.connections.values_mut() {
+                    let was_closed = connection.is_closed();
+
+                    connection.on_tick(&mut nic)?;
+
+                    any_closed |= !was_closed && connection.is_closed();
+                }
+
+                if any_closed {
+                    // wake up anyone blocked on a connection that just timed out
+                    ih.connect_var.notify_all();
+                    ih.recv_var.notify_all();
+                    ih.send_var.notify_all();
+                }
 
-            for connection in cmg.connections.values_mut() {
-                connection.on_tick(&mut nic)?;
+                cmg.connections.retain(|_, c| !c.is_reapable());
             }
+        }
+
+        use nix::poll::PollFlags;
+
+        let pfd = nix::poll::PollFd::new(nic.poll_fd(), PollFlags::POLLIN);
 
+        let n = nix::poll::poll(&mut [pfd], timeout)?;
+
+        assert_ne!(n, -1);
+
+        if n == 0 {
             continue;
         }
 
@@ -85,6 +219,10 @@ fn packet_loop(mut nic: Iface, ih: Arc<InterfaceHandle>) -> Result<()> {
 
         let nbytes = nic.recv(&mut buf)?;
 
+        if nbytes == 0 {
+            continue;
+        }
+
         let ip_h = match Ipv4HeaderSlice::from_slice(&buf[..nbytes]) {
             Ok(ip_header) => ip_header,
             Err(_) => {
@@ -93,10 +231,23 @@ fn packet_loop(mut nic: Iface, ih: Arc<InterfaceHandle>) -> Result<()> {
             }
         };
 
+        if ip_h.to_header().calc_header_checksum() != ip_h.header_checksum() {
+            eprintln!("Bad IP header checksum.");
+            continue;
+        }
+
+        // anything past the IP packet is link layer padding
+        let nbytes = nbytes.min(ip_h.total_len() as usize);
+
         let src = ip_h.source_addr();
 
         let dst = ip_h.destination_addr();
 
+        if dst != addr {
+            eprintln!("Packet for another host.");
+            continue;
+        }
+
         if ip_h.protocol() != TCP {
             eprintln!("Non-TCP packet.");
             continue;
@@ -115,8 +266,17 @@ fn packet_loop(mut nic: Iface, ih: Arc<InterfaceHandle>) -> Result<()> {
         // First byte of TCP payload
         let datai = ip_h.slice().len() + tcp_h.slice().len();
 
+        if tcp_h.calc_checksum_ipv4(&ip_h, &buf[datai..nbytes]).ok() != Some(tcp_h.checksum()) {
+            eprintln!("Bad TCP checksum.");
+            continue;
+        }
+
         let mut cmg = ih.manager.lock().unwrap();
 
+        if cmg.terminate {
+            return Ok(());
+        }
+
         let cm = &mut *cmg;
 
         let q = Quad {
@@ -128,15 +288,38 @@ fn packet_loop(mut nic: Iface, ih: Arc<InterfaceHandle>) -> Result<()> {
             Some(connection) => {
                 println!("Got packet from known quad {:?}", q);
 
+                let was_synchronized = connection.is_synchronized();
+
                 let a = connection
                     .on_packet(&mut nic, tcp_h, &buf[datai..nbytes])
                     .unwrap();
 
+                if !was_synchronized && connection.is_synchronized() {
+                    if !connection.is_passive() {
+                        ih.connect_var.notify_all();
+                    } else if connection.is_closed() {
+                        // reset before accept() ever saw it, so nobody needs to hear about it
+                        cm.connections.remove(&q);
+                        continue;
+                    } else if let Some(listener) = cm.listeners.get_mut(&q.dest.1) {
+                        // handshake done, the connection can now be handed out by accept()
+                        listener.pending.push_back(q);
+                        ih.pending_var.notify_all();
+                    } else {
+                        // the listener went away while the handshake was in progress
+                        connection.abort(&mut nic)?;
+                        cm.connections.remove(&q);
+                        continue;
+                    }
+                }
+
                 if a.contains(tcp::Available::READ) {
                     ih.recv_var.notify_all();
                 }
 
-                if a.contains(tcp::Available::WRITE) {}
+                if a.contains(tcp::Available::WRITE) {
+                    ih.send_var.notify_all();
+                }
             }
 
             None => {
@@ -147,18 +330,16 @@ fn packet_loop(mut nic: Iface, ih: Arc<InterfaceHandle>) -> Result<()> {
 
                 let nic = &mut nic;
 
-                if let Some(pending) = cm.pending.get_mut(destination_port) {
-                    if let Some(c) = Connection::accept(nic, ip_h, tcp_h)
+                if tcp_h.rst() {
+                    // never answer a reset with a reset
+                } else if cm.listeners.contains_key(destination_port) && !tcp_h.ack() {
+                    if let Some(c) = Connection::accept(nic, ip_h, tcp_h, cm.config.clone())
                         .expect("Failed to accept incoming connection.")
                     {
                         cm.connections.insert(q, c);
-
-                        pending.push_back(q);
-
-                        drop(cmg);
-
-                        ih.pending_var.notify_all();
                     }
+                } else {
+                    tcp::reset_unknown(nic, cm.config.ttl, q, &tcp_h, nbytes - datai)?;
                 }
             }
         }
@@ -166,28 +347,127 @@ fn packet_loop(mut nic: Iface, ih: Arc<InterfaceHandle>) -> Result<()> {
 }
 
 impl Interface {
+    /// Runs the stack on the TUN device `tun0` (see run.sh), with the defaults of
+    /// [`InterfaceBuilder`].
     pub fn new() -> Result<Self> {
-        let mode = tun_tap::Mode::Tun;
+        InterfaceBuilder::new().build()
+    }
 
-        let nic = tun_tap::Iface::without_packet_info("tun0", mode)?;
+    /// Runs the stack on `device` with the defaults of [`InterfaceBuilder`], sizing
+    /// segments to fit its MTU.
+    pub fn with_device<D: Device>(device: D) -> Result<Self> {
+        InterfaceBuilder::new().build_with_device(device)
+    }
 
-        let ih: Arc<InterfaceHandle> = Default::default();
+    /// Sets the maximum segment lifetime used for connections created from now on.
+    /// Actively closed connections are kept in TIME-WAIT for twice this long.
+    ///
+    /// Fails with `InvalidInput` for a zero `msl`.
+    pub fn set_msl(&mut self, msl: Duration) -> Result<()> {
+        builder::check_msl(msl)?;
 
-        let loop_handler = {
-            let ih = ih.clone();
-            thread::spawn(move || packet_loop(nic, ih))
-        };
+        self.ih.as_mut().unwrap().manager.lock().unwrap().config.msl = msl;
 
-        Ok(Interface {
-            ih: Some(ih),
-            jh: Some(loop_handler),
-        })
+        Ok(())
+    }
+
+    /// Sets the clock that the timers of connections created from now on run off (the
+    /// system's monotonic clock by default). A [`ManualClock`] makes them only move when
+    /// the test driving them says so.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.ih
+            .as_mut()
+            .unwrap()
+            .manager
+            .lock()
+            .unwrap()
+            .config
+            .clock = Arc::new(clock);
+    }
+
+    /// Replaces the initial sequence number generator (RFC 6528 by default) for
+    /// connections created from now on.
+    pub fn set_iss_generator(&mut self, generator: impl IssGenerator + 'static) {
+        self.ih.as_mut().unwrap().manager.lock().unwrap().config.iss = Arc::new(generator);
+    }
+
+    /// Sets the congestion control algorithm (NewReno by default) for connections created
+    /// from now on. Each connection starts out with its own copy of `algorithm`.
+    pub fn set_congestion_control<C>(&mut self, algorithm: C)
+    where
+        C: CongestionControl + Clone + Sync + 'static,
+    {
+        self.ih
+            .as_mut()
+            .unwrap()
+            .manager
+            .lock()
+            .unwrap()
+            .config
+            .congestion = Arc::new(move || Box::new(algorithm.clone()));
+    }
+
+    /// Sets how many received bytes each connection created from now on may buffer
+    /// before the application reads them. This is what bounds the advertised window.
+    ///
+    /// Fails with `InvalidInput` if `size` is 0 or more than any window can cover.
+    pub fn set_recv_buffer_size(&mut self, size: usize) -> Result<()> {
+        builder::check_recv_buffer(size)?;
+
+        self.ih
+            .as_mut()
+            .unwrap()
+            .manager
+            .lock()
+            .unwrap()
+            .config
+            .recv_buffer = size;
+
+        Ok(())
+    }
+
+    /// Sets how many written bytes each connection created from now on may queue for
+    /// sending before writes block.
+    ///
+    /// Fails with `InvalidInput` if `size` is 0.
+    pub fn set_send_buffer_size(&mut self, size: usize) -> Result<()> {
+        builder::check_send_buffer(size)?;
+
+        self.ih
+            .as_mut()
+            .unwrap()
+            .manager
+            .lock()
+            .unwrap()
+            .config
+            .send_buffer = size;
+
+        Ok(())
+    }
+
+    /// Sets how long connections created from now on may hold back the ACK for received
+    /// data, in the hope of sending it along with data of their own.
+    ///
+    /// Fails with `InvalidInput` unless `delay` is less than 500ms (RFC 5681 4.2).
+    pub fn set_ack_delay(&mut self, delay: Duration) -> Result<()> {
+        builder::check_ack_delay(delay)?;
+
+        self.ih
+            .as_mut()
+            .unwrap()
+            .manager
+            .lock()
+            .unwrap()
+            .config
+            .ack_delay = delay;
+
+        Ok(())
     }
 
     pub fn bind(&mut self, port: u16) -> Result<TcpListener> {
         let mut cm = self.ih.as_mut().unwrap().manager.lock().unwrap();
 
-        match cm.pending.get(&port) {
+        match cm.listeners.get(&port) {
             Some(_) => {
                 return Err(io::Error::new(
                     io::ErrorKind::AddrInUse,
@@ -196,7 +476,7 @@ impl Interface {
             }
 
             None => {
-                cm.pending.insert(port, VecDeque::new());
+                cm.listeners.insert(port, Listener::default());
             }
         };
 
@@ -207,6 +487,61 @@ impl Interface {
             ih: self.ih.as_mut().unwrap().clone(),
         })
     }
+
+    /// Opens a connection to `addr` from an ephemeral local port, blocking until the
+    /// three-way handshake completes or fails. `addr` has to be on the interface's subnet.
+    pub fn connect(&mut self, addr: (Ipv4Addr, u16)) -> Result<TcpStream> {
+        let ih = self.ih.as_mut().unwrap().clone();
+
+        let mut cm = ih.manager.lock().unwrap();
+
+        let local = cm.config.addr;
+
+        let mask = u32::from(cm.config.netmask);
+
+        if u32::from(addr.0) & mask != u32::from(local) & mask {
+            return Err(io::Error::new(
+                io::ErrorKind::NetworkUnreachable,
+                "Address is not on the interface's subnet",
+            ));
+        }
+
+        let port = cm.ephemeral_port().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::AddrNotAvailable, "No ephemeral ports left")
+        })?;
+
+        let quad = Quad {
+            src: addr,
+            dest: (local, port),
+        };
+
+        let c = tcp::Connection::connect(quad, cm.config.clone());
+
+        cm.connections.insert(quad, c);
+
+        loop {
+            let c = cm.connections.get(&quad).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "Stream was terminated unexpectedly",
+                )
+            })?;
+
+            if let Some(kind) = c.error {
+                cm.connections.remove(&quad);
+                return Err(io::Error::new(kind, "Failed to connect"));
+            }
+
+            if c.is_synchronized() {
+                return Ok(TcpStream {
+                    quad,
+                    ih: ih.clone(),
+                });
+            }
+
+            cm = ih.connect_var.wait(cm).unwrap();
+        }
+    }
 }
 
 pub struct TcpListener {
@@ -218,37 +553,97 @@ impl Drop for TcpListener {
     fn drop(&mut self) {
         let mut cm = self.ih.manager.lock().unwrap();
 
-        let pending = cm
-            .pending
+        let listener = cm
+            .listeners
             .remove(&self.port)
             .expect("port closed while listener still active");
 
-        for _quad in pending {
-            unimplemented!();
+        for quad in listener.pending {
+            if let Some(c) = cm.connections.get_mut(&quad) {
+                // nobody is going to accept() these anymore
+                c.detached = true;
+
+                let _ = c.close();
+            }
         }
     }
 }
 
 impl TcpListener {
+    /// Waits for an incoming connection, unless the listener is in non-blocking mode or
+    /// has a read timeout; see [`TcpListener::set_nonblocking`].
     pub fn accept(&mut self) -> Result<TcpStream> {
         let mut cm = self.ih.manager.lock().unwrap();
 
+        let mut deadline = None;
+
         loop {
-            if let Some(quad) = cm
-                .pending
+            let listener = cm
+                .listeners
                 .get_mut(&self.port)
-                .expect("Port closed while listener still active")
-                .pop_front()
-            {
+                .expect("Port closed while listener still active");
+
+            if let Some(quad) = listener.pending.pop_front() {
+                let write_timeout = listener.write_timeout;
+
+                if let Some(c) = cm.connections.get_mut(&quad) {
+                    c.write_timeout = write_timeout;
+                }
+
                 return Ok(TcpStream {
                     quad,
                     ih: self.ih.clone(),
                 });
             }
 
-            cm = self.ih.pending_var.wait(cm).unwrap();
+            let deadline = *deadline.get_or_insert_with(|| deadline_after(listener.read_timeout));
+
+            let nonblocking = listener.nonblocking;
+
+            cm = wait(&self.ih.pending_var, cm, nonblocking, deadline)?;
         }
     }
+
+    /// Runs `f` on this listener's options.
+    fn with_listener<T>(&self, f: impl FnOnce(&mut Listener) -> T) -> T {
+        let mut cm = self.ih.manager.lock().unwrap();
+
+        f(cm.listeners
+            .get_mut(&self.port)
+            .expect("Port closed while listener still active"))
+    }
+
+    /// Puts the listener into or out of non-blocking mode. In non-blocking mode `accept`
+    /// fails with `WouldBlock` instead of waiting when no connection is ready.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        self.with_listener(|l| l.nonblocking = nonblocking);
+        Ok(())
+    }
+
+    /// Limits how long `accept` waits for a connection before failing with `TimedOut`.
+    /// `None`, the default, waits forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        check_timeout(timeout)?;
+        self.with_listener(|l| l.read_timeout = timeout);
+        Ok(())
+    }
+
+    pub fn read_timeout(&self) -> Result<Option<Duration>> {
+        Ok(self.with_listener(|l| l.read_timeout))
+    }
+
+    /// Sets the write timeout that streams accepted from now on start out with, see
+    /// [`TcpStream::set_write_timeout`]. A listener never writes anything itself, so
+    /// this is all a write timeout can mean for it. `None`, the default, waits forever.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        check_timeout(timeout)?;
+        self.with_listener(|l| l.write_timeout = timeout);
+        Ok(())
+    }
+
+    pub fn write_timeout(&self) -> Result<Option<Duration>> {
+        Ok(self.with_listener(|l| l.write_timeout))
+    }
 }
 
 pub struct TcpStream {
@@ -256,10 +651,29 @@ pub struct TcpStream {
     ih: Arc<InterfaceHandle>,
 }
 
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let mut cm = self.ih.manager.lock().unwrap();
+
+        if let Some(c) = cm.connections.get_mut(&self.quad) {
+            c.detached = true;
+
+            // errors only mean we're already on our way out
+            let _ = c.close();
+
+            if c.is_reapable() {
+                cm.connections.remove(&self.quad);
+            }
+        }
+    }
+}
+
 impl Read for TcpStream {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         let mut cm = self.ih.manager.lock().unwrap();
 
+        let mut deadline = None;
+
         loop {
             let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
                 io::Error::new(
@@ -268,6 +682,10 @@ impl Read for TcpStream {
                 )
             })?;
 
+            if let Some(kind) = c.error {
+                return Err(kind.into());
+            }
+
             if c.is_recv_closed() && c.incoming.is_empty() {
                 return Ok(0);
             }
@@ -294,7 +712,11 @@ impl Read for TcpStream {
                 return Ok(nread);
             }
 
-            cm = self.ih.recv_var.wait(cm).unwrap();
+            let deadline = *deadline.get_or_insert_with(|| deadline_after(c.read_timeout));
+
+            let nonblocking = c.nonblocking;
+
+            cm = wait(&self.ih.recv_var, cm, nonblocking, deadline)?;
         }
     }
 }
@@ -303,50 +725,92 @@ impl Write for TcpStream {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         let mut cm = self.ih.manager.lock().unwrap();
 
-        let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "Stream was terminated unexpectedly",
-            )
-        })?;
+        let mut deadline = None;
 
-        if c.unacked.len() >= SENDQUEUE_SIZE {
-            return Err(io::Error::new(
-                io::ErrorKind::WouldBlock,
-                "Too many bytes buffered",
-            ));
-        }
+        loop {
+            let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "Stream was terminated unexpectedly",
+                )
+            })?;
+
+            if let Some(kind) = c.error {
+                return Err(kind.into());
+            }
+
+            if c.closed || c.is_closed() {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "Stream was shut down for writing",
+                ));
+            }
+
+            if buf.is_empty() {
+                return Ok(0);
+            }
+
+            let nwrite = buf.len().min(c.send_space());
+
+            if nwrite > 0 {
+                c.unacked.extend(buf[..nwrite].iter());
+
+                return Ok(nwrite);
+            }
 
-        let nwrite = min(buf.len(), SENDQUEUE_SIZE - c.unacked.len());
+            let deadline = *deadline.get_or_insert_with(|| deadline_after(c.write_timeout));
 
-        c.unacked.extend(buf[..nwrite].iter());
+            let nonblocking = c.nonblocking;
 
-        Ok(nwrite)
+            cm = wait(&self.ih.send_var, cm, nonblocking, deadline)?;
+        }
     }
 
+    /// Waits until the peer has acknowledged everything written so far, within the write
+    /// timeout if one is set. Data held back by Nagle's algorithm or
+    /// [`TcpStream::set_cork`] is sent right away.
     fn flush(&mut self) -> Result<()> {
+        self.with_connection(|c| c.push())?;
+
         let mut cm = self.ih.manager.lock().unwrap();
 
-        let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "Stream was terminated unexpectedly",
-            )
-        })?;
+        let mut deadline = None;
 
-        if c.unacked.is_empty() {
-            Ok(())
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "Stream was terminated unexpectedly",
-            ))
+        loop {
+            let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "Stream was terminated unexpectedly",
+                )
+            })?;
+
+            if let Some(kind) = c.error {
+                return Err(kind.into());
+            }
+
+            if c.unacked.is_empty() {
+                return Ok(());
+            }
+
+            if c.is_closed() {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "Stream was closed with data unacknowledged",
+                ));
+            }
+
+            let deadline = *deadline.get_or_insert_with(|| deadline_after(c.write_timeout));
+
+            let nonblocking = c.nonblocking;
+
+            cm = wait(&self.ih.send_var, cm, nonblocking, deadline)?;
         }
     }
 }
 
 impl TcpStream {
-    pub fn shutdown(&self, _how: Shutdown) -> Result<()> {
+    /// Runs `f` on this stream's connection.
+    fn with_connection<T>(&self, f: impl FnOnce(&mut tcp::Connection) -> T) -> Result<T> {
         let mut cm = self.ih.manager.lock().unwrap();
 
         let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
@@ -356,6 +820,136 @@ impl TcpStream {
             )
         })?;
 
-        c.close()
+        Ok(f(c))
+    }
+
+    pub fn shutdown(&self, _how: Shutdown) -> Result<()> {
+        self.with_connection(|c| c.close())?
+    }
+
+    /// Puts the stream into or out of non-blocking mode. In non-blocking mode reads and
+    /// writes fail with `WouldBlock` instead of waiting for data or buffer space.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        self.with_connection(|c| c.nonblocking = nonblocking)
+    }
+
+    /// Limits how long a read waits for data before failing with `TimedOut`. `None`, the
+    /// default, waits forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        check_timeout(timeout)?;
+        self.with_connection(|c| c.read_timeout = timeout)
+    }
+
+    pub fn read_timeout(&self) -> Result<Option<Duration>> {
+        self.with_connection(|c| c.read_timeout)
+    }
+
+    /// Limits how long a write waits for send buffer space before failing with
+    /// `TimedOut`. `None`, the default, waits forever.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        check_timeout(timeout)?;
+        self.with_connection(|c| c.write_timeout = timeout)
+    }
+
+    pub fn write_timeout(&self) -> Result<Option<Duration>> {
+        self.with_connection(|c| c.write_timeout)
+    }
+
+    /// Switches this connection over to a different congestion control algorithm, which
+    /// starts again from its initial window.
+    pub fn set_congestion_control(
+        &self,
+        algorithm: impl CongestionControl + 'static,
+    ) -> Result<()> {
+        self.with_connection(|c| c.set_congestion_control(Box::new(algorithm)))
+    }
+
+    /// Turns quick ACKs on or off (off by default). Normally the ACK for received data is
+    /// held back for a bit in case it can go out together with data of our own; with
+    /// quick ACKs on, every segment is acknowledged as soon as it arrives.
+    pub fn set_quickack(&self, quickack: bool) -> Result<()> {
+        self.with_connection(|c| c.set_quickack(quickack))
+    }
+
+    /// Whether quick ACKs are on; see [`TcpStream::set_quickack`].
+    pub fn quickack(&self) -> Result<bool> {
+        self.with_connection(|c| c.quickack())
+    }
+
+    /// Turns Nagle's algorithm off or back on, like `std::net::TcpStream::set_nodelay`.
+    /// With Nagle on (the default), small writes are held back while earlier data is
+    /// still unacknowledged, so they can go out together in one segment.
+    pub fn set_nodelay(&self, nodelay: bool) -> Result<()> {
+        self.with_connection(|c| c.set_nodelay(nodelay))
+    }
+
+    /// Whether Nagle's algorithm is off; see [`TcpStream::set_nodelay`].
+    pub fn nodelay(&self) -> Result<bool> {
+        self.with_connection(|c| c.nodelay())
+    }
+
+    /// Corks or uncorks the stream. While corked, only full-sized segments are sent, even
+    /// with nothing in flight; whatever is left goes out once uncorked, flushed or shut
+    /// down.
+    pub fn set_cork(&self, cork: bool) -> Result<()> {
+        self.with_connection(|c| c.set_cork(cork))
+    }
+
+    /// Whether the stream is corked; see [`TcpStream::set_cork`].
+    pub fn cork(&self) -> Result<bool> {
+        self.with_connection(|c| c.cork())
+    }
+
+    /// Turns keepalive probes on or off (off by default). Once the connection has been
+    /// idle for `idle`, the peer gets probed every keepalive interval; after the keepalive
+    /// count of probes went unanswered the connection is aborted, and reads and writes
+    /// fail with `TimedOut`.
+    pub fn set_keepalive(&self, idle: Option<Duration>) -> Result<()> {
+        if idle == Some(Duration::ZERO) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot set a 0 duration keepalive",
+            ));
+        }
+
+        self.with_connection(|c| c.set_keepalive(idle))
+    }
+
+    /// The idle time after which keepalive probes start, `None` if they are off.
+    pub fn keepalive(&self) -> Result<Option<Duration>> {
+        self.with_connection(|c| c.keepalive())
+    }
+
+    /// Sets the time between keepalive probes (75 seconds by default).
+    pub fn set_keepalive_interval(&self, interval: Duration) -> Result<()> {
+        if interval.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot set a 0 duration keepalive interval",
+            ));
+        }
+
+        self.with_connection(|c| c.set_keepalive_interval(interval))
+    }
+
+    pub fn keepalive_interval(&self) -> Result<Duration> {
+        self.with_connection(|c| c.keepalive_interval())
+    }
+
+    /// Sets how many keepalive probes may go unanswered before the connection is aborted
+    /// (9 by default).
+    pub fn set_keepalive_count(&self, count: u32) -> Result<()> {
+        if count == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "keepalive count must be at least 1",
+            ));
+        }
+
+        self.with_connection(|c| c.set_keepalive_count(count))
+    }
+
+    pub fn keepalive_count(&self) -> Result<u32> {
+        self.with_connection(|c| c.keepalive_count())
     }
 }
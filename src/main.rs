@@ -13,7 +13,7 @@ fn main() -> Result<()> {
         while let Ok(mut stream) = l1.accept() {
             println!("Got a connection!");
 
-            stream.write(b"hello from rust-tcp!\n").unwrap();
+            stream.write_all(b"hello from rust-tcp!\n").unwrap();
 
             stream.shutdown(std::net::Shutdown::Write).unwrap();
 
@@ -1,14 +1,42 @@
 use bitflags::bitflags;
+use etherparse::TcpOptionElement;
 use std::{
     cmp::min,
-    collections::{BTreeMap, VecDeque},
+    collections::VecDeque,
     io::{self, Write},
+    net::Ipv4Addr,
+    sync::Arc,
     time,
 };
-use tun_tap::Iface;
+
+use crate::clock::{Clock, MonotonicClock};
+use crate::Quad;
+use options::{Options, DEFAULT_MSS};
+use reassembly::Reassembly;
+use sack::Scoreboard;
+
+mod congestion;
+mod iss;
+mod options;
+mod reassembly;
+mod sack;
+#[cfg(test)]
+mod tests;
+
+pub use congestion::{CongestionControl, Cubic, NewReno, Reno};
+pub use iss::{FixedIss, IssGenerator, Rfc6528Iss};
+
+/// Builds the congestion controller for each new connection.
+pub(crate) type CongestionFactory = dyn Fn() -> Box<dyn CongestionControl> + Send + Sync;
 
 use std::io::Result;
 
+/// Where a connection's outgoing IP packets go. Connections never do any I/O of their
+/// own; whoever feeds them segments and ticks passes in the sink to send through.
+pub(crate) trait PacketSink {
+    fn send(&mut self, packet: &[u8]) -> Result<()>;
+}
+
 bitflags! {
     pub(crate) struct Available: u32 {
         const READ = 0b00000001;
@@ -16,13 +44,100 @@ bitflags! {
     }
 }
 
+/// Per-interface settings that every connection picks up when it is created.
+#[derive(Clone)]
+pub(crate) struct Config {
+    /// Maximum segment lifetime; closed connections linger in TIME-WAIT for twice this long.
+    pub(crate) msl: time::Duration,
+    pub(crate) iss: Arc<dyn IssGenerator>,
+    /// What all timers run off.
+    pub(crate) clock: Arc<dyn Clock>,
+    /// Bounds for the retransmission timeout (RFC 6298 2.4 and 2.5).
+    pub(crate) min_rto: time::Duration,
+    pub(crate) max_rto: time::Duration,
+    /// Consecutive retransmission timeouts after which an established connection is aborted.
+    pub(crate) max_retries: u32,
+    /// Like `max_retries`, but for our SYN or SYN,ACK during the handshake.
+    pub(crate) max_syn_retries: u32,
+    pub(crate) congestion: Arc<CongestionFactory>,
+    /// Most out-of-order data held per connection while waiting for a gap to fill.
+    pub(crate) reassembly_limit: usize,
+    /// How much received data may be buffered for the application; bounds our window.
+    pub(crate) recv_buffer: usize,
+    /// How much written data may wait to be sent and acknowledged before writes block.
+    pub(crate) send_buffer: usize,
+    /// Largest IP packet the interface carries; bounds the MSS we offer and the one we use.
+    pub(crate) mtu: usize,
+    /// Longest we hold back the ACK for in-order data, hoping to piggyback it.
+    pub(crate) ack_delay: time::Duration,
+    /// The address the interface answers to, and the netmask of the subnet it is on.
+    pub(crate) addr: Ipv4Addr,
+    pub(crate) netmask: Ipv4Addr,
+    /// Time to live of the packets we send.
+    pub(crate) ttl: u8,
+    /// The `G` of RFC 6298: how often `on_tick` gets called.
+    pub(crate) tick: time::Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            msl: time::Duration::from_secs(30),
+            iss: Arc::new(Rfc6528Iss::default()),
+            clock: Arc::new(MonotonicClock),
+            min_rto: time::Duration::from_secs(1),
+            max_rto: time::Duration::from_secs(60),
+            max_retries: 12,
+            max_syn_retries: 6,
+            congestion: Arc::new(|| Box::new(NewReno::default())),
+            reassembly_limit: 64 * 1024,
+            recv_buffer: 64 * 1024,
+            send_buffer: 1024,
+            mtu: 1500,
+            ack_delay: time::Duration::from_millis(40),
+            addr: Ipv4Addr::new(192, 168, 0, 2),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            ttl: 64,
+            tick: time::Duration::from_millis(1),
+        }
+    }
+}
+
+/// Size of the IP and TCP headers without options, which the MTU has to fit besides the MSS.
+const HEADERS_LEN: usize = 20 + 20;
+
+/// Smallest MSS we take from the peer, like Linux's `TCP_MIN_MSS`. Anything less would
+/// leave little or no room for data next to the timestamp and SACK options.
+const MIN_MSS: usize = 88;
+
+/// Largest window scale shift allowed (RFC 7323 2.3).
+const MAX_WSCALE: u8 = 14;
+
+/// Smallest MTU we run on: what every IPv4 host has to accept (RFC 791), and what the
+/// default MSS assumes.
+pub(crate) const MIN_MTU: usize = DEFAULT_MSS as usize + HEADERS_LEN;
+
+/// Largest receive buffer the window can cover, even scaled as far as it goes.
+pub(crate) const MAX_RECV_BUFFER: usize = (u16::MAX as usize) << MAX_WSCALE;
+
+/// An ACK has to go out in less than this (RFC 5681 4.2).
+pub(crate) const MAX_ACK_DELAY: time::Duration = time::Duration::from_millis(500);
+
+/// RTO to use before the first RTT measurement (RFC 6298 2.1).
+const INITIAL_RTO: time::Duration = time::Duration::from_secs(1);
+
 #[derive(Debug)]
 enum State {
+    SynSent,
     SynRcvd,
     Estab,
     FinWait1,
     FinWait2,
+    Closing,
     TimeWait,
+    CloseWait,
+    LastAck,
+    Closed,
 }
 
 pub struct Connection {
@@ -32,26 +147,241 @@ pub struct Connection {
     ip_h: etherparse::Ipv4Header,
     tcp_h: etherparse::TcpHeader,
     timers: Timers,
+    config: Config,
+    congestion: Box<dyn CongestionControl>,
+    recovery: Recovery,
+    /// Effective send MSS: the most data we put in one segment (RFC 9293 3.7.1).
+    mss: u32,
+    /// Whether window scaling is in use, or still on offer while the handshake is going on.
+    window_scaling: bool,
+    timestamps: Timestamps,
+    /// Whether SACK is in use, or still on offer while the handshake is going on.
+    sack: bool,
+    scoreboard: Scoreboard,
+    /// Duplicate data we just received, to report in our next ACK (RFC 2883).
+    dsack: Option<(u32, u32)>,
+    delayed_ack: DelayedAck,
+    /// Whether Nagle's algorithm is off.
+    nodelay: bool,
+    /// Whether to hold back small segments even when nothing is in flight.
+    cork: bool,
+    /// Where the data the application last flushed ends. Nagle and the cork don't hold
+    /// back anything before it.
+    push: Option<u32>,
+    keepalive: Keepalive,
 
     pub(crate) unacked: VecDeque<u8>,
     pub(crate) incoming: VecDeque<u8>,
+    reassembly: Reassembly,
 
     pub(crate) closed: bool,
     closed_at: Option<u32>,
+
+    /// Whether this connection came from a listening port rather than `Interface::connect`.
+    passive: bool,
+
+    /// Set when the connection was torn down abnormally, e.g. by a RST from the peer.
+    pub(crate) error: Option<io::ErrorKind>,
+
+    /// Set once no `TcpStream` refers to this connection anymore, so it can be reaped as
+    /// soon as the state machine reaches CLOSED.
+    pub(crate) detached: bool,
+
+    /// Socket options of the `TcpStream`: whether reads and writes fail with `WouldBlock`
+    /// instead of waiting, and how long they wait before failing with `TimedOut`.
+    pub(crate) nonblocking: bool,
+    pub(crate) read_timeout: Option<time::Duration>,
+    pub(crate) write_timeout: Option<time::Duration>,
 }
 
 struct Timers {
-    send_times: BTreeMap<u32, time::Instant>,
-    srtt: f64,
+    /// End sequence number and send time of every segment still in flight that has been
+    /// transmitted exactly once, oldest first. Retransmissions clear it, as their
+    /// ACKs are ambiguous (Karn's algorithm).
+    send_times: VecDeque<(u32, time::Instant)>,
+    srtt: Option<time::Duration>,
+    rttvar: time::Duration,
+    rto: time::Duration,
+    /// When the retransmission timer was last (re)started, `None` when it isn't running.
+    rto_started: Option<time::Instant>,
+    /// Retransmission timeouts in a row without the peer acknowledging anything.
+    retries: u32,
+    /// When we (last) entered TIME-WAIT.
+    time_wait: Option<time::Instant>,
+    /// When we entered FIN-WAIT-2.
+    fin_wait2: Option<time::Instant>,
+    /// When the persist timer was last (re)started, `None` while the peer's window is open.
+    persist: Option<time::Instant>,
+    /// How long until the next window probe; backs off like the RTO.
+    persist_interval: time::Duration,
+}
+
+/// Timestamps option state (RFC 7323).
+struct Timestamps {
+    /// Whether the option is in use, or still on offer while the handshake is going on.
+    enabled: bool,
+    /// Start of our timestamp clock, which ticks once per millisecond.
+    epoch: time::Instant,
+    /// Added to our clock so the values we send don't give away how long we've been up.
+    offset: u32,
+    /// The peer's timestamp to echo back (`TS.Recent`).
+    recent: u32,
+    /// When `recent` was last updated.
+    recent_at: time::Instant,
+    /// The acknowledgment number of the last segment we sent (`Last.ACK.sent`).
+    last_ack_sent: u32,
+}
+
+impl Timestamps {
+    /// Our timestamp clock's value at `now` (`TSval`).
+    fn tsval(&self, now: time::Instant) -> u32 {
+        self.offset
+            .wrapping_add(now.saturating_duration_since(self.epoch).as_millis() as u32)
+    }
+
+    /// Whether `recent` is still good for PAWS; it goes stale after 24 days of silence,
+    /// by when the peer's clock may have wrapped around (RFC 7323 5.5).
+    fn recent_valid(&self, now: time::Instant) -> bool {
+        now.saturating_duration_since(self.recent_at) < PAWS_IDLE
+    }
+}
+
+/// How long `TS.Recent` stays valid without being updated.
+const PAWS_IDLE: time::Duration = time::Duration::from_secs(24 * 24 * 60 * 60);
+
+/// Room a timestamps option takes up in every segment, including the padding.
+const TIMESTAMPS_LEN: u32 = 12;
+
+/// An ACK we owe the peer but are holding back (RFC 9293 3.8.6.3).
+struct DelayedAck {
+    /// In-order data received since we last sent an ACK.
+    bytes: u32,
+    /// When that data started coming in, `None` if we owe no ACK.
+    since: Option<time::Instant>,
+    /// Whether to acknowledge everything right away instead.
+    quick: bool,
+}
+
+/// Keepalive settings and state (RFC 9293 3.8.4).
+struct Keepalive {
+    /// How long the connection may sit idle before we start probing, `None` when off.
+    idle: Option<time::Duration>,
+    /// Time between probes that go unanswered.
+    interval: time::Duration,
+    /// Unanswered probes after which we give up on the peer.
+    count: u32,
+    /// Probes sent since we last heard from the peer.
+    probes: u32,
+    /// When we last got an acceptable segment.
+    last_heard: time::Instant,
+}
+
+/// Keepalive defaults, the same as Linux's.
+const KEEPALIVE_INTERVAL: time::Duration = time::Duration::from_secs(75);
+const KEEPALIVE_COUNT: u32 = 9;
+
+/// Duplicate ACK bookkeeping for fast retransmit and fast recovery (RFC 5681 3.2, RFC 6582).
+struct Recovery {
+    /// Duplicate ACKs received in a row.
+    dup_acks: u32,
+    /// The highest sequence number sent when recovery last started (`recover` in RFC 6582).
+    recover: u32,
+    /// Whether we are in fast recovery right now.
+    active: bool,
+    /// Where to carry on resending after a retransmission timeout, which presumes
+    /// everything in flight lost. `None` once it has all been sent again.
+    resend: Option<u32>,
+    /// The end of what we resent during this recovery (`HighRxt` in RFC 6675).
+    high_rxt: u32,
+}
+
+/// Duplicate ACKs that trigger a fast retransmit.
+const DUP_ACK_THRESHOLD: u32 = 3;
+
+impl Timers {
+    fn new() -> Self {
+        Timers {
+            send_times: Default::default(),
+            srtt: None,
+            rttvar: time::Duration::ZERO,
+            rto: INITIAL_RTO,
+            rto_started: None,
+            retries: 0,
+            time_wait: None,
+            fin_wait2: None,
+            persist: None,
+            persist_interval: INITIAL_RTO,
+        }
+    }
+
+    /// Folds a round-trip time measurement into SRTT/RTTVAR and recomputes the RTO
+    /// (RFC 6298 2.2 and 2.3).
+    fn sample_rtt(&mut self, r: time::Duration, config: &Config) {
+        let srtt = match self.srtt {
+            None => {
+                self.rttvar = r / 2;
+                r
+            }
+            Some(srtt) => {
+                self.rttvar = self.rttvar * 3 / 4 + srtt.abs_diff(r) / 4;
+                srtt * 7 / 8 + r / 8
+            }
+        };
+
+        self.srtt = Some(srtt);
+
+        self.rto =
+            (srtt + (4 * self.rttvar).max(config.tick)).clamp(config.min_rto, config.max_rto);
+    }
+
+    fn rto_expired(&self, now: time::Instant) -> bool {
+        self.rto_started
+            .is_some_and(|t| now.saturating_duration_since(t) >= self.rto)
+    }
+
+    /// Backs the timer off after it expired (RFC 6298 5.5 and 5.6).
+    fn back_off(&mut self, config: &Config, now: time::Instant) {
+        self.rto = (self.rto * 2).min(config.max_rto);
+        self.retries += 1;
+        self.send_times.clear();
+        self.rto_started = Some(now);
+    }
 }
 
 impl Connection {
+    /// Whether the peer has sent its FIN, i.e. no more data will arrive.
     pub fn is_recv_closed(&self) -> bool {
-        if let State::TimeWait = self.state {
-            true
-        } else {
-            false
-        }
+        matches!(
+            self.state,
+            State::CloseWait | State::LastAck | State::Closing | State::TimeWait | State::Closed
+        )
+    }
+
+    /// Whether the three-way handshake has completed (or the connection was torn down).
+    pub(crate) fn is_synchronized(&self) -> bool {
+        !matches!(self.state, State::SynSent | State::SynRcvd)
+    }
+
+    pub(crate) fn is_passive(&self) -> bool {
+        self.passive
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        matches!(self.state, State::Closed)
+    }
+
+    /// Whether the connection is finished with and nobody is left to observe it.
+    pub(crate) fn is_reapable(&self) -> bool {
+        self.detached && self.is_closed()
+    }
+
+    fn now(&self) -> time::Instant {
+        self.config.clock.now()
+    }
+
+    /// How long ago `t` was.
+    fn since(&self, t: time::Instant) -> time::Duration {
+        self.now().saturating_duration_since(t)
     }
 
     pub fn availability(&self) -> Available {
@@ -61,73 +391,286 @@ impl Connection {
             a |= Available::READ;
         }
 
+        if self.is_closed() || self.send_space() > 0 {
+            a |= Available::WRITE;
+        }
+
         a
     }
 
-    pub fn accept(
-        nic: &mut Iface,
-        ip_header: etherparse::Ipv4HeaderSlice,
-        tcp_header: etherparse::TcpHeaderSlice,
-    ) -> Result<Option<Self>> {
-        if !tcp_header.syn() {
-            return Ok(None);
-        }
+    /// How many more bytes may be queued for sending.
+    pub(crate) fn send_space(&self) -> usize {
+        self.config.send_buffer.saturating_sub(self.unacked.len())
+    }
 
-        let iss = 0;
+    fn new(quad: Quad, state: State, config: Config) -> Self {
+        let iss = config.iss.generate(quad.dest, quad.src);
 
-        let wnd = 1024;
+        let mss = min(DEFAULT_MSS as u32, (config.mtu - HEADERS_LEN) as u32);
 
-        let mut c = Connection {
-            timers: Timers {
-                send_times: Default::default(),
-                srtt: time::Duration::from_secs(1 * 60).as_secs_f64(),
+        let mut congestion = (config.congestion)();
+
+        congestion.set_mss(mss);
+
+        let reassembly = Reassembly::new(config.reassembly_limit);
+
+        // windows in SYNs are never scaled
+        let wnd = min(config.recv_buffer, u16::MAX as usize) as u32;
+
+        // the smallest shift that lets us offer the whole buffer (RFC 7323 2.3)
+        let wscale = (0..MAX_WSCALE)
+            .find(|&shift| config.recv_buffer >> shift <= u16::MAX as usize)
+            .unwrap_or(MAX_WSCALE);
+
+        let now = config.clock.now();
+
+        let ttl = config.ttl;
+
+        Connection {
+            timers: Timers::new(),
+            config,
+            congestion,
+            recovery: Recovery {
+                dup_acks: 0,
+                recover: iss,
+                active: false,
+                resend: None,
+                high_rxt: iss,
+            },
+            mss,
+            window_scaling: true,
+            timestamps: Timestamps {
+                enabled: true,
+                epoch: now,
+                // the ISS is as unpredictable as it gets
+                offset: iss,
+                recent: 0,
+                recent_at: now,
+                last_ack_sent: 0,
             },
-            state: State::SynRcvd,
+            sack: true,
+            scoreboard: Scoreboard::new(),
+            dsack: None,
+            delayed_ack: DelayedAck {
+                bytes: 0,
+                since: None,
+                quick: false,
+            },
+            nodelay: false,
+            cork: false,
+            push: None,
+            keepalive: Keepalive {
+                idle: None,
+                interval: KEEPALIVE_INTERVAL,
+                count: KEEPALIVE_COUNT,
+                probes: 0,
+                last_heard: now,
+            },
+            state,
             send: SendSequenceSpace {
                 iss,
                 una: iss,
                 nxt: iss,
-                wnd,
+                wnd: 0,
+                wscale: 0,
+                wl1: 0,
+                wl2: iss,
             },
             recv: RecvSequenceSpace {
-                nxt: tcp_header.sequence_number() + 1,
-                wnd: tcp_header.window_size(),
+                nxt: 0,
+                wnd,
+                wscale,
             },
             ip_h: etherparse::Ipv4Header::new(
                 0,
-                64,
+                ttl,
                 etherparse::IpNumber::TCP,
-                ip_header.destination(),
-                ip_header.source(),
+                quad.dest.0.octets(),
+                quad.src.0.octets(),
             )
             .expect("Failed to create IP header"),
 
-            tcp_h: etherparse::TcpHeader::new(
-                tcp_header.destination_port(),
-                tcp_header.source_port(),
-                iss,
-                wnd,
-            ),
+            tcp_h: etherparse::TcpHeader::new(quad.dest.1, quad.src.1, iss, wnd as u16),
             incoming: Default::default(),
+            reassembly,
             unacked: Default::default(),
             closed: false,
             closed_at: None,
+            passive: false,
+            error: None,
+            detached: false,
+            nonblocking: false,
+            read_timeout: None,
+            write_timeout: None,
+        }
+    }
+
+    pub fn accept(
+        sink: &mut dyn PacketSink,
+        ip_header: etherparse::Ipv4HeaderSlice,
+        tcp_header: etherparse::TcpHeaderSlice,
+        config: Config,
+    ) -> Result<Option<Self>> {
+        if !tcp_header.syn() {
+            return Ok(None);
+        }
+
+        let quad = Quad {
+            src: (ip_header.source_addr(), tcp_header.source_port()),
+            dest: (ip_header.destination_addr(), tcp_header.destination_port()),
         };
 
+        let mut c = Connection::new(quad, State::SynRcvd, config);
+
+        c.passive = true;
+
+        c.recv.nxt = tcp_header.sequence_number().wrapping_add(1);
+
+        c.send.wnd = tcp_header.window_size() as u32;
+        c.send.wl1 = tcp_header.sequence_number();
+
+        c.negotiate(&Options::parse(&tcp_header));
+
         c.tcp_h.syn = true;
 
         c.tcp_h.ack = true;
 
-        c.write(nic, c.send.nxt, 0)?;
+        c.write(sink, c.send.nxt, 0)?;
 
         Ok(Some(c))
     }
 
-    fn write(&mut self, nic: &Iface, seq: u32, mut limit: usize) -> Result<usize> {
-        let mut buf = [0u8; 1500];
+    /// Starts an active open towards `quad.src`. The SYN itself goes out on the next tick.
+    pub(crate) fn connect(quad: Quad, config: Config) -> Self {
+        Connection::new(quad, State::SynSent, config)
+    }
+
+    /// Takes on what the peer's SYN says about how to talk to it.
+    fn negotiate(&mut self, options: &Options) {
+        let peer_mss = (options.mss.unwrap_or(DEFAULT_MSS) as usize).max(MIN_MSS);
+
+        self.mss = min(peer_mss, self.config.mtu - HEADERS_LEN) as u32;
+
+        // scaling is only used if both sides asked for it (RFC 7323 2.2)
+        match options.wscale {
+            Some(shift) if self.window_scaling => self.send.wscale = min(shift, MAX_WSCALE),
+            _ => {
+                self.window_scaling = false;
+                self.recv.wscale = 0;
+            }
+        }
+
+        // and so are timestamps (RFC 7323 3.2)
+        match options.timestamp {
+            Some((tsval, _)) if self.timestamps.enabled => {
+                self.timestamps.recent = tsval;
+                self.timestamps.recent_at = self.now();
+
+                // every segment carries the option from now on, leaving less room for data
+                self.mss -= TIMESTAMPS_LEN;
+            }
+            _ => self.timestamps.enabled = false,
+        }
+
+        self.sack &= options.sack_permitted;
+
+        self.congestion.set_mss(self.mss);
+    }
+
+    /// The options to put on the segment `write` is about to build.
+    fn segment_options(&self) -> Vec<TcpOptionElement> {
+        let mut options = Vec::new();
+
+        if self.tcp_h.syn {
+            // the largest segment we can receive without the IP layer fragmenting it
+            let mss = min(self.config.mtu - HEADERS_LEN, u16::MAX as usize) as u16;
+
+            options.push(TcpOptionElement::MaximumSegmentSize(mss));
+
+            if self.window_scaling {
+                options.push(TcpOptionElement::Noop);
+                options.push(TcpOptionElement::WindowScale(self.recv.wscale));
+            }
+        }
+
+        if self.timestamps.enabled {
+            options.push(TcpOptionElement::Noop);
+            options.push(TcpOptionElement::Noop);
+            options.push(TcpOptionElement::Timestamp(
+                self.timestamps.tsval(self.now()),
+                self.timestamps.recent,
+            ));
+        }
+
+        if self.tcp_h.syn {
+            if self.sack {
+                options.push(TcpOptionElement::Noop);
+                options.push(TcpOptionElement::Noop);
+                options.push(TcpOptionElement::SelectiveAcknowledgementPermitted);
+            }
+        } else if let Some((&first, rest)) = self.sack_blocks().split_first() {
+            let mut others = [None; 3];
+
+            for (other, &block) in others.iter_mut().zip(rest) {
+                *other = Some(block);
+            }
+
+            options.push(TcpOptionElement::Noop);
+            options.push(TcpOptionElement::Noop);
+            options.push(TcpOptionElement::SelectiveAcknowledgement(first, others));
+        }
+
+        options
+    }
+
+    /// The SACK blocks for our next segment: a DSACK block if we just got duplicate data
+    /// (RFC 2883), then what's waiting in the reassembly queue.
+    fn sack_blocks(&self) -> Vec<(u32, u32)> {
+        if !self.sack {
+            return Vec::new();
+        }
+
+        let mut blocks: Vec<_> = self
+            .dsack
+            .into_iter()
+            .chain(self.reassembly.sack_blocks())
+            .collect();
+
+        // all that fits next to a timestamp
+        blocks.truncate(if self.timestamps.enabled { 3 } else { 4 });
+
+        blocks
+    }
+
+    /// How much data fits in a segment besides its options.
+    fn segment_room(&self) -> u32 {
+        match self.sack_blocks().len() {
+            0 => self.mss,
+            n => self.mss - 4 - 8 * n as u32,
+        }
+    }
+
+    fn write(&mut self, sink: &mut dyn PacketSink, seq: u32, mut limit: usize) -> Result<usize> {
+        let mut buf = vec![0u8; self.config.mtu];
+
+        self.tcp_h
+            .set_options(&self.segment_options())
+            .expect("options don't fit in the TCP header");
+
+        self.dsack = None;
 
         self.tcp_h.sequence_number = seq;
         self.tcp_h.acknowledgment_number = self.recv.nxt;
+        self.timestamps.last_ack_sent = self.recv.nxt;
+
+        // whatever we send carries the ACK we may have been holding back
+        self.delayed_ack.bytes = 0;
+        self.delayed_ack.since = None;
+        self.tcp_h.window_size = if self.tcp_h.syn {
+            min(self.recv.wnd, u16::MAX as u32) as u16
+        } else {
+            min(self.recv.wnd >> self.recv.wscale, u16::MAX as u32) as u16
+        };
 
         let mut offset = seq.wrapping_sub(self.send.una) as usize;
 
@@ -138,6 +681,17 @@ impl Connection {
             }
         }
 
+        if wrapping_lt(seq, self.send.una) {
+            // a probe, see `send_probe`
+            offset = 0;
+            limit = 0;
+        }
+
+        // our SYN takes up sequence space without being in `unacked`, so while it's
+        // outstanding SND.NXT lies past the end of the data; from there on there is
+        // nothing to send but a bare segment
+        offset = min(offset, self.unacked.len());
+
         let (mut h, mut t) = self.unacked.as_slices();
 
         if h.len() >= offset {
@@ -167,7 +721,7 @@ impl Connection {
 
         let ip_header_end = buf_len - unwritten.len();
 
-        unwritten = &mut unwritten[self.tcp_h.header_len() as usize..];
+        unwritten = &mut unwritten[self.tcp_h.header_len()..];
 
         let tcp_header_end = buf_len - unwritten.len();
 
@@ -209,13 +763,21 @@ impl Connection {
             self.tcp_h.fin = false;
         }
 
+        if next_seq != seq {
+            if seq == self.send.nxt {
+                self.timers.send_times.push_back((next_seq, self.now()));
+            }
+
+            if self.timers.rto_started.is_none() {
+                self.timers.rto_started = Some(self.now());
+            }
+        }
+
         if wrapping_lt(self.send.nxt, next_seq) {
             self.send.nxt = next_seq;
         }
 
-        self.timers.send_times.insert(seq, time::Instant::now());
-
-        nic.send(&buf[..payload_ends_at])?;
+        sink.send(&buf[..payload_ends_at])?;
 
         Ok(payload_bytes)
     }
@@ -226,7 +788,13 @@ impl Connection {
             State::SynRcvd | State::Estab => {
                 self.state = State::FinWait1;
             }
+            State::CloseWait => {
+                self.state = State::LastAck;
+            }
             State::FinWait1 | State::FinWait2 => {}
+            State::SynSent => {
+                self.state = State::Closed;
+            }
             _ => {
                 return Err(io::Error::new(
                     io::ErrorKind::NotConnected,
@@ -237,220 +805,1024 @@ impl Connection {
         Ok(())
     }
 
-    pub(crate) fn on_tick(&mut self, nic: &mut Iface) -> Result<()> {
-        if let State::FinWait2 | State::TimeWait = self.state {
+    pub(crate) fn on_tick(&mut self, sink: &mut dyn PacketSink) -> Result<()> {
+        if let State::Closed = self.state {
+            // timed out, reset or finished; whatever was still pending went with it
             return Ok(());
         }
 
-        let nunacked_data = self
-            .closed_at
-            .unwrap_or(self.send.nxt)
-            .wrapping_sub(self.send.una);
-
-        let nunsent_data = self.unacked.len() as u32 - nunacked_data;
-
-        let waited_for = self
-            .timers
-            .send_times
-            .range(self.send.una..)
-            .next()
-            .map(|t| t.1.elapsed());
-
-        let should_retransmit = if let Some(waited_for) = waited_for {
-            waited_for > time::Duration::from_secs(1)
-                && waited_for.as_secs_f64() > 1.5 * self.timers.srtt
-        } else {
-            false
-        };
-
-        if should_retransmit {
-            let resend = min(self.unacked.len() as u32, self.send.wnd as u32);
-
-            if resend < self.send.wnd as u32 && self.closed {
-                self.tcp_h.fin = true;
-                self.closed_at = Some(self.send.una.wrapping_add(self.unacked.len() as u32));
-            }
+        if let State::TimeWait = self.state {
+            let expired = self
+                .timers
+                .time_wait
+                .is_some_and(|t| self.since(t) >= 2 * self.config.msl);
 
-            self.write(nic, self.send.una, resend as usize)?;
-        } else {
-            if nunsent_data == 0 && self.closed_at.is_some() {
-                return Ok(());
+            if expired {
+                self.state = State::Closed;
             }
 
-            let allowed = self.send.wnd as u32 - nunacked_data;
+            return Ok(());
+        }
 
-            if allowed == 0 {
+        if let State::FinWait2 = self.state {
+            // with the stream gone nobody is waiting for the peer's FIN, which may never
+            // come; give up on it after as long as TIME-WAIT lasts (like Linux's
+            // tcp_fin_timeout)
+            let expired = self.detached
+                && self
+                    .timers
+                    .fin_wait2
+                    .is_some_and(|t| self.since(t) >= 2 * self.config.msl);
+
+            if expired {
+                self.state = State::Closed;
                 return Ok(());
             }
+        }
 
-            let send = min(nunsent_data, allowed);
-
-            if send < allowed && self.closed && self.closed_at.is_none() {
-                self.tcp_h.fin = true;
-                self.closed_at = Some(self.send.una.wrapping_add(self.unacked.len() as u32));
+        if let State::Estab | State::FinWait1 | State::FinWait2 = self.state {
+            if self.refresh_window() {
+                // let the peer know there's room again
+                self.write(sink, self.send.nxt, 0)?;
             }
+        }
 
-            self.write(nic, self.send.nxt, send as usize)?;
+        if let State::FinWait2 = self.state {
+            return self.send_delayed_ack(sink);
         }
 
-        Ok(())
-    }
+        if let State::SynSent | State::SynRcvd = self.state {
+            if self.timers.rto_expired(self.now()) {
+                if self.timers.retries >= self.config.max_syn_retries {
+                    self.time_out();
+                    return Ok(());
+                }
 
-    pub(crate) fn on_packet(
-        &mut self,
-        nic: &mut Iface,
-        tcp_header: etherparse::TcpHeaderSlice,
-        data: &[u8],
-    ) -> Result<Available> {
-        let seqn = tcp_header.sequence_number();
+                self.timers.back_off(&self.config, self.now());
+            } else if self.send.nxt != self.send.iss {
+                return Ok(());
+            }
 
-        let mut slen = data.len() as u32;
+            // nothing but our SYN can be in flight until the handshake completes
+            self.tcp_h.syn = true;
+            self.write(sink, self.send.iss, 0)?;
 
-        if tcp_header.fin() {
-            slen += 1
-        };
+            return Ok(());
+        }
 
-        if tcp_header.syn() {
-            slen += 1
-        };
+        if let State::Estab | State::CloseWait = self.state {
+            self.check_keepalive(sink)?;
+
+            if self.is_closed() {
+                return Ok(());
+            }
+        }
 
-        let wend = self.recv.nxt.wrapping_add(self.recv.wnd as u32);
+        let stalled = self.send.wnd == 0
+            && (self.send.una != self.send.nxt
+                || !self.unacked.is_empty()
+                || (self.closed && self.closed_at.is_none()));
+
+        if stalled {
+            // there's no point retransmitting into a zero window; we keep asking the peer
+            // for a window update instead (RFC 9293 3.8.6.1). The probes go on forever,
+            // answered or not; only keepalive, if it's on, gives up on a silent peer
+            self.timers.rto_started = None;
+
+            match self.timers.persist {
+                None => {
+                    self.timers.persist = Some(self.now());
+                    self.timers.persist_interval = self.timers.rto;
+                }
+                Some(t) if self.since(t) >= self.timers.persist_interval => {
+                    self.send_probe(sink)?;
 
-        let okay = if slen == 0 {
-            // zero-length segment has separate rules for acceptance
-            if self.recv.wnd == 0 {
-                if seqn != self.recv.nxt {
-                    false
-                } else {
-                    true
+                    self.timers.persist = Some(self.now());
+                    self.timers.persist_interval =
+                        (self.timers.persist_interval * 2).min(self.config.max_rto);
                 }
-            } else if !is_between_wrapped(self.recv.nxt.wrapping_sub(1), seqn, wend) {
-                false
-            } else {
-                true
-            }
-        } else {
-            if self.recv.wnd == 0 {
-                false
-            } else if !is_between_wrapped(self.recv.nxt.wrapping_sub(1), seqn, wend)
-                && !is_between_wrapped(
-                    self.recv.nxt.wrapping_sub(1),
-                    seqn.wrapping_add(slen - 1),
-                    wend,
-                )
-            {
-                false
-            } else {
-                true
+                Some(_) => {}
             }
-        };
 
-        if !okay {
-            eprintln!("NOT OKAY");
-            self.write(nic, self.send.nxt, 0)?;
-            return Ok(self.availability());
+            return self.send_delayed_ack(sink);
         }
 
-        if !tcp_header.ack() {
-            if tcp_header.syn() {
-                // got SYN part of initial handshake
-                assert!(data.is_empty());
-                self.recv.nxt = seqn.wrapping_add(1);
+        if self.timers.persist.take().is_some() && self.send.una != self.send.nxt {
+            // the window opened, so whatever is outstanding is due for retransmission again
+            self.timers.rto_started = Some(self.now());
+        }
+
+        if self.timers.rto_expired(self.now()) {
+            if self.timers.retries >= self.config.max_retries {
+                self.time_out();
+                return Ok(());
             }
 
-            return Ok(self.availability());
-        }
+            self.timers.back_off(&self.config, self.now());
 
-        let ackn = tcp_header.acknowledgment_number();
+            self.congestion
+                .on_timeout(self.send.nxt.wrapping_sub(self.send.una), self.now());
 
-        if let State::SynRcvd = self.state {
-            if is_between_wrapped(
-                self.send.una.wrapping_sub(1),
-                ackn,
-                self.send.nxt.wrapping_add(1),
-            ) {
-                self.state = State::Estab;
-            }
+            // a timeout ends fast recovery, and nothing sent so far may trigger it again
+            self.recovery.active = false;
+            self.recovery.dup_acks = 0;
+            self.recovery.recover = self.send.nxt;
+
+            // the peer may have reneged on what it SACKed (RFC 2018 8)
+            self.scoreboard.clear();
+
+            // all of it is resent, starting with a single segment (RFC 5681 3.1)
+            self.recovery.resend = Some(self.send.una);
         }
 
-        if let State::Estab | State::FinWait1 | State::FinWait2 = self.state {
-            if is_between_wrapped(self.send.una, ackn, self.send.nxt.wrapping_add(1)) {
-                println!(
-                    "ack for {} (last: {}); prune in {:?}",
-                    ackn, self.send.una, self.unacked
-                );
-                if !self.unacked.is_empty() {
-                    let data_start = if self.send.una == self.send.iss {
-                        // send.una hasn't been updated yet with ACK for our SYN, so data starts just beyond it
-                        self.send.una.wrapping_add(1)
-                    } else {
-                        self.send.una
-                    };
+        self.send_segments(sink)?;
 
-                    let acked_data_end =
-                        min(ackn.wrapping_sub(data_start) as _, self.unacked.len());
+        self.send_delayed_ack(sink)
+    }
 
-                    self.unacked.drain(..acked_data_end);
+    /// Acknowledges `n` bytes of in-order data: right away if that makes two full-sized
+    /// segments' worth (RFC 5681 4.2) or quick ACKs are on, otherwise on the next segment
+    /// we send or once `Config::ack_delay` is up, whichever comes first.
+    fn delay_ack(&mut self, sink: &mut dyn PacketSink, n: usize) -> Result<()> {
+        let now = self.now();
 
-                    self.timers.send_times.retain(|&seq, sent| {
-                        if is_between_wrapped(self.send.una, seq, ackn) {
-                            self.timers.srtt =
-                                0.8 * self.timers.srtt + (1.0 - 0.8) * sent.elapsed().as_secs_f64();
-                            false
-                        } else {
-                            true
-                        }
-                    });
-                }
-                self.send.una = ackn;
-            }
+        self.delayed_ack.bytes += n as u32;
+        self.delayed_ack.since.get_or_insert(now);
+
+        if self.delayed_ack.quick || self.delayed_ack.bytes >= 2 * self.mss {
+            self.write(sink, self.send.nxt, 0)?;
         }
 
-        if let State::FinWait1 = self.state {
-            if let Some(closed_at) = self.closed_at {
-                if self.send.una == closed_at.wrapping_add(1) {
-                    // our FIN has been ACKed!
+        Ok(())
+    }
+
+    /// Probes a peer we haven't heard from in a while, and gives up on it once enough
+    /// probes went unanswered.
+    fn check_keepalive(&mut self, sink: &mut dyn PacketSink) -> Result<()> {
+        let Some(idle) = self.keepalive.idle else {
+            return Ok(());
+        };
+
+        // only an idle connection needs probing; otherwise retransmissions do the job
+        if self.send.una != self.send.nxt {
+            return Ok(());
+        }
+
+        let due = idle + self.keepalive.interval * self.keepalive.probes;
+
+        if self.since(self.keepalive.last_heard) < due {
+            return Ok(());
+        }
+
+        if self.keepalive.probes >= self.keepalive.count {
+            send_rst(sink, self.config.ttl, self.quad(), self.send.nxt, None)?;
+            self.time_out();
+            return Ok(());
+        }
+
+        self.send_probe(sink)?;
+        self.keepalive.probes += 1;
+
+        Ok(())
+    }
+
+    pub(crate) fn set_keepalive(&mut self, idle: Option<time::Duration>) {
+        self.keepalive.idle = idle;
+    }
+
+    pub(crate) fn keepalive(&self) -> Option<time::Duration> {
+        self.keepalive.idle
+    }
+
+    pub(crate) fn set_keepalive_interval(&mut self, interval: time::Duration) {
+        self.keepalive.interval = interval;
+    }
+
+    pub(crate) fn keepalive_interval(&self) -> time::Duration {
+        self.keepalive.interval
+    }
+
+    pub(crate) fn set_keepalive_count(&mut self, count: u32) {
+        self.keepalive.count = count;
+    }
+
+    pub(crate) fn keepalive_count(&self) -> u32 {
+        self.keepalive.count
+    }
+
+    /// Sends a segment with a sequence number the peer has already seen, which it has to
+    /// answer with an ACK telling us its current window.
+    fn send_probe(&mut self, sink: &mut dyn PacketSink) -> Result<()> {
+        self.write(sink, self.send.una.wrapping_sub(1), 0)?;
+
+        Ok(())
+    }
+
+    /// Sends the ACK we've been holding back if it can't wait any longer.
+    fn send_delayed_ack(&mut self, sink: &mut dyn PacketSink) -> Result<()> {
+        let due = self
+            .delayed_ack
+            .since
+            .is_some_and(|t| self.since(t) >= self.config.ack_delay);
+
+        if due {
+            self.write(sink, self.send.nxt, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Turns quick ACKs on or off; when on, in-order data is acknowledged right away.
+    pub(crate) fn set_quickack(&mut self, quick: bool) {
+        self.delayed_ack.quick = quick;
+    }
+
+    pub(crate) fn quickack(&self) -> bool {
+        self.delayed_ack.quick
+    }
+
+    pub(crate) fn set_nodelay(&mut self, nodelay: bool) {
+        self.nodelay = nodelay;
+    }
+
+    pub(crate) fn nodelay(&self) -> bool {
+        self.nodelay
+    }
+
+    pub(crate) fn set_cork(&mut self, cork: bool) {
+        self.cork = cork;
+    }
+
+    pub(crate) fn cork(&self) -> bool {
+        self.cork
+    }
+
+    /// Sends everything written so far without waiting for it to fill up segments.
+    pub(crate) fn push(&mut self) {
+        self.push = Some(self.send.una.wrapping_add(self.unacked.len() as u32));
+    }
+
+    /// Sends segments for as long as `next_segment` comes up with one.
+    fn send_segments(&mut self, sink: &mut dyn PacketSink) -> Result<()> {
+        while let Some((seq, len, fin)) = self.next_segment() {
+            self.send_segment(sink, seq, len, fin)?;
+        }
+
+        Ok(())
+    }
+
+    /// Picks what to send next as `(seq, len, fin)`, if the windows leave room for it:
+    /// during SACK loss recovery whatever NextSeg() of RFC 6675 chooses, after a
+    /// retransmission timeout the rest of what was in flight, and otherwise new data.
+    fn next_segment(&mut self) -> Option<(u32, u32, bool)> {
+        if self.recovery.active && self.sack {
+            // the window stays at ssthresh throughout recovery (RFC 6675 5 step 4.2)
+            if self.pipe() + self.mss > self.congestion.ssthresh() {
+                return None;
+            }
+
+            let from = if wrapping_lt(self.recovery.high_rxt, self.send.una) {
+                self.send.una
+            } else {
+                self.recovery.high_rxt
+            };
+
+            let hole = self.scoreboard.next_unsacked(from);
+
+            let below_high = self
+                .scoreboard
+                .high()
+                .is_some_and(|high| wrapping_lt(hole, high));
+
+            if below_high && self.scoreboard.is_lost(hole, self.mss) {
+                return Some(self.retransmission(hole, u32::MAX));
+            }
+
+            let in_flight = self.send.nxt.wrapping_sub(self.send.una);
+
+            if let Some(segment) = self.new_segment(self.send.wnd.saturating_sub(in_flight)) {
+                return Some(segment);
+            }
+
+            return below_high.then(|| self.retransmission(hole, u32::MAX));
+        }
+
+        if let Some(resend) = self.recovery.resend {
+            // the peer may have acknowledged or SACKed some of it in the meantime
+            let resend = if wrapping_lt(resend, self.send.una) {
+                self.send.una
+            } else {
+                resend
+            };
+
+            let seq = self.scoreboard.next_unsacked(resend);
+
+            if wrapping_lt(seq, self.send.nxt) {
+                self.recovery.resend = Some(seq);
+
+                let allowed = self
+                    .send_window()
+                    .saturating_sub(seq.wrapping_sub(self.send.una));
+
+                return (allowed > 0).then(|| self.retransmission(seq, allowed));
+            }
+
+            self.recovery.resend = None;
+        }
+
+        let in_flight = self.send.nxt.wrapping_sub(self.send.una);
+
+        self.new_segment(self.send_window().saturating_sub(in_flight))
+    }
+
+    /// The next segment of new data, given that `allowed` more bytes may go out. Our FIN
+    /// goes right after the last of the data once the application has closed.
+    fn new_segment(&self, allowed: u32) -> Option<(u32, u32, bool)> {
+        // our data and FIN are all out already
+        if self.closed_at.is_some() {
+            return None;
+        }
+
+        let in_flight = self.send.nxt.wrapping_sub(self.send.una);
+        let pending = self.unacked.len() as u32 - in_flight;
+
+        let len = min(min(pending, allowed), self.segment_room());
+
+        // Nagle: a small segment waits until everything before it has been acknowledged,
+        // or until uncorked, so it has a chance to grow (RFC 9293 3.7.4); once closed
+        // nothing more is coming, though, and neither is anything the application flushed
+        let pushed = self
+            .push
+            .is_some_and(|push| wrapping_lt(self.send.nxt, push));
+
+        let held = len < self.segment_room()
+            && !self.closed
+            && !pushed
+            && (self.cork || (!self.nodelay && in_flight > 0));
+
+        if held {
+            return None;
+        }
+
+        let fin = self.closed && len == pending && len < allowed;
+
+        (len > 0 || fin).then_some((self.send.nxt, len, fin))
+    }
+
+    /// The segment to resend from `seq`, given that `allowed` more bytes may go out. It
+    /// stops short of anything the peer has SACKed.
+    fn retransmission(&self, seq: u32, allowed: u32) -> (u32, u32, bool) {
+        let data_end = self.send.una.wrapping_add(self.unacked.len() as u32);
+
+        let mut len = min(
+            min(data_end.wrapping_sub(seq), allowed),
+            self.segment_room(),
+        );
+
+        if let Some(sacked) = self.scoreboard.next_sacked(seq) {
+            len = min(len, sacked.wrapping_sub(seq));
+        }
+
+        let fin = self.closed && seq.wrapping_add(len) == data_end && len < allowed;
+
+        (seq, len, fin)
+    }
+
+    /// Sends `len` bytes from `seq`, and our FIN after them if `fin`.
+    fn send_segment(
+        &mut self,
+        sink: &mut dyn PacketSink,
+        seq: u32,
+        len: u32,
+        fin: bool,
+    ) -> Result<()> {
+        if fin {
+            self.tcp_h.fin = true;
+            self.closed_at = Some(seq.wrapping_add(len));
+        }
+
+        let end = seq.wrapping_add(len + fin as u32);
+
+        if wrapping_lt(seq, self.send.nxt) {
+            // Karn: the retransmitted segments can't give us RTT samples anymore
+            while let Some(&(sent_end, _)) = self.timers.send_times.front() {
+                if wrapping_lt(end, sent_end) {
+                    break;
+                }
+
+                self.timers.send_times.pop_front();
+            }
+
+            if wrapping_lt(self.recovery.high_rxt, end) {
+                self.recovery.high_rxt = end;
+            }
+
+            if self.recovery.resend.is_some() {
+                self.recovery.resend = Some(end);
+            }
+        }
+
+        self.write(sink, seq, len as usize)?;
+
+        Ok(())
+    }
+
+    /// SetPipe() of RFC 6675: how much of what we sent is presumably still in the network.
+    fn pipe(&self) -> u32 {
+        let mut pipe = 0;
+
+        for (start, end) in self.scoreboard.holes(self.send.una, self.send.nxt) {
+            if !self.scoreboard.is_lost(start, self.mss) {
+                pipe += end.wrapping_sub(start);
+            }
+
+            // and once more for what we resent
+            if wrapping_lt(start, self.recovery.high_rxt) {
+                let resent_end = if wrapping_lt(end, self.recovery.high_rxt) {
+                    end
+                } else {
+                    self.recovery.high_rxt
+                };
+
+                pipe += resent_end.wrapping_sub(start);
+            }
+        }
+
+        pipe
+    }
+
+    /// Advances RCV.NXT over `n` bytes that were just added to `incoming`. The window
+    /// shrinks by as much, keeping its right edge where it was.
+    fn take_in(&mut self, n: usize) {
+        self.recv.nxt = self.recv.nxt.wrapping_add(n as u32);
+        self.recv.wnd = self.recv.wnd.saturating_sub(n as u32);
+    }
+
+    /// Reopens the receive window once the application has freed up a sizeable chunk of
+    /// buffer space, so we never offer the peer a silly window (RFC 9293 3.8.6.2.2).
+    /// Returns whether the window opened.
+    fn refresh_window(&mut self) -> bool {
+        let free = min(
+            self.config.recv_buffer.saturating_sub(self.incoming.len()),
+            (u16::MAX as usize) << self.recv.wscale,
+        );
+
+        // never 0, or a window that hasn't grown would be announced all over again
+        let threshold = min(self.config.recv_buffer / 2, self.mss as usize).max(1);
+
+        if free >= self.recv.wnd as usize + threshold {
+            self.recv.wnd = free as u32;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// How much we may have in flight: the lesser of the peer's and the congestion window.
+    fn send_window(&self) -> u32 {
+        let mut cwnd = self.congestion.cwnd();
+
+        if !self.recovery.active {
+            // limited transmit: each of the first two duplicate ACKs lets one more new
+            // segment out, to keep the ACK clock going (RFC 3042)
+            cwnd += min(self.recovery.dup_acks, DUP_ACK_THRESHOLD - 1) * self.mss;
+        }
+
+        min(self.send.wnd, cwnd)
+    }
+
+    /// Resends the segment starting at SND.UNA.
+    fn retransmit_first(&mut self, sink: &mut dyn PacketSink) -> Result<()> {
+        let (seq, len, fin) = self.retransmission(self.send.una, u32::MAX);
+
+        self.send_segment(sink, seq, len, fin)
+    }
+
+    fn on_dup_ack(&mut self, sink: &mut dyn PacketSink) -> Result<()> {
+        self.recovery.dup_acks += 1;
+
+        // with SACK, the scoreboard can tell of a loss before enough duplicates arrive
+        let lost = self.recovery.dup_acks == DUP_ACK_THRESHOLD
+            || (self.sack && self.scoreboard.is_lost(self.send.una, self.mss));
+
+        if self.recovery.active {
+            if self.sack {
+                // the scoreboard changed, so there may be room for more
+                self.send_segments(sink)?;
+            } else {
+                self.congestion.on_recovery_dup_ack();
+            }
+        } else if lost && wrapping_lt(self.recovery.recover, self.send.una) {
+            self.congestion
+                .on_fast_retransmit(self.send.nxt.wrapping_sub(self.send.una), self.now());
+
+            self.recovery.active = true;
+            self.recovery.recover = self.send.nxt;
+            self.recovery.high_rxt = self.send.una;
+
+            self.retransmit_first(sink)?;
+
+            if self.sack {
+                self.send_segments(sink)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The timestamp echoed in a segment with `options`, if we use timestamps.
+    fn tsecr(&self, options: &Options) -> Option<u32> {
+        options
+            .timestamp
+            .filter(|_| self.timestamps.enabled)
+            .map(|(_, tsecr)| tsecr)
+    }
+
+    pub(crate) fn set_congestion_control(&mut self, mut congestion: Box<dyn CongestionControl>) {
+        congestion.set_mss(self.mss);
+        self.congestion = congestion;
+    }
+
+    fn quad(&self) -> Quad {
+        Quad {
+            src: (self.ip_h.destination.into(), self.tcp_h.destination_port),
+            dest: (self.ip_h.source.into(), self.tcp_h.source_port),
+        }
+    }
+
+    fn enter_time_wait(&mut self) {
+        self.state = State::TimeWait;
+        self.timers.time_wait = Some(self.now());
+    }
+
+    /// Aborts the connection, letting the peer know with a RST.
+    pub(crate) fn abort(&mut self, sink: &mut dyn PacketSink) -> Result<()> {
+        if let State::SynRcvd
+        | State::Estab
+        | State::FinWait1
+        | State::FinWait2
+        | State::CloseWait = self.state
+        {
+            send_rst(sink, self.config.ttl, self.quad(), self.send.nxt, None)?;
+        }
+
+        self.state = State::Closed;
+
+        Ok(())
+    }
+
+    /// Tears the connection down in response to an acceptable RST from the peer.
+    fn reset(&mut self) {
+        self.error = match self.state {
+            // a passive open just goes back to listening
+            State::SynRcvd if self.passive => None,
+            State::SynRcvd => Some(io::ErrorKind::ConnectionRefused),
+            State::Estab | State::FinWait1 | State::FinWait2 | State::CloseWait => {
+                Some(io::ErrorKind::ConnectionReset)
+            }
+            _ => None,
+        };
+
+        self.state = State::Closed;
+
+        self.unacked.clear();
+        self.incoming.clear();
+    }
+
+    /// Advances SND.UNA to `ackn`, which must acknowledge new data, and updates the
+    /// retransmission timer accordingly.
+    /// `tsecr` is the timestamp echoed on the ACK, if timestamps are in use.
+    fn on_ack(&mut self, sink: &mut dyn PacketSink, ackn: u32, tsecr: Option<u32>) -> Result<()> {
+        let acked = ackn.wrapping_sub(self.send.una);
+
+        self.send.una = ackn;
+
+        self.scoreboard.trim(ackn);
+
+        if self.push.is_some_and(|push| !wrapping_lt(ackn, push)) {
+            self.push = None;
+        }
+
+        let mut sample = None;
+
+        while let Some(&(end, sent)) = self.timers.send_times.front() {
+            if wrapping_lt(ackn, end) {
+                break;
+            }
+
+            sample = Some(self.since(sent));
+            self.timers.send_times.pop_front();
+        }
+
+        if let Some(tsecr) = tsecr {
+            // the echo tells which transmission got acknowledged, so this works for
+            // retransmissions too (RFC 7323 4.1)
+            let rtt = self.timestamps.tsval(self.now()).wrapping_sub(tsecr);
+
+            sample = Some(time::Duration::from_millis(rtt as u64));
+        }
+
+        if let Some(r) = sample {
+            self.timers.sample_rtt(r, &self.config);
+        }
+
+        self.timers.retries = 0;
+
+        self.timers.rto_started = if self.send.una == self.send.nxt {
+            None
+        } else {
+            Some(self.now())
+        };
+
+        self.recovery.dup_acks = 0;
+
+        if !self.recovery.active {
+            self.congestion.on_ack(acked, self.timers.srtt, self.now());
+        } else if wrapping_lt(ackn, self.recovery.recover) && self.sack {
+            // the scoreboard has it covered (RFC 6675 5 step C)
+            self.send_segments(sink)?;
+        } else if wrapping_lt(ackn, self.recovery.recover) && self.congestion.on_partial_ack(acked)
+        {
+            // partial ACK: the next hole is right at SND.UNA (RFC 6582 3.2 step 5)
+            self.retransmit_first(sink)?;
+        } else {
+            self.recovery.active = false;
+            self.congestion
+                .on_recovery_exit(self.send.nxt.wrapping_sub(self.send.una));
+        }
+
+        Ok(())
+    }
+
+    /// Gives up on a peer that stopped acknowledging our retransmissions.
+    fn time_out(&mut self) {
+        if matches!(self.state, State::SynRcvd) && self.passive {
+            // nobody has seen this connection yet, so there's nobody to tell either
+            self.detached = true;
+        } else {
+            self.error = Some(io::ErrorKind::TimedOut);
+        }
+
+        self.state = State::Closed;
+
+        self.unacked.clear();
+        self.incoming.clear();
+    }
+
+    /// Handles a segment arriving while our SYN is outstanding (RFC 9293 3.10.7.3).
+    fn on_syn_sent_packet(
+        &mut self,
+        sink: &mut dyn PacketSink,
+        tcp_header: etherparse::TcpHeaderSlice,
+    ) -> Result<Available> {
+        let ackn = tcp_header.acknowledgment_number();
+
+        let ack_ok = tcp_header.ack()
+            && is_between_wrapped(self.send.iss, ackn, self.send.nxt.wrapping_add(1));
+
+        if tcp_header.ack() && !ack_ok {
+            if !tcp_header.rst() {
+                send_rst(sink, self.config.ttl, self.quad(), ackn, None)?;
+            }
+
+            return Ok(self.availability());
+        }
+
+        if tcp_header.rst() {
+            if ack_ok {
+                self.error = Some(io::ErrorKind::ConnectionRefused);
+                self.state = State::Closed;
+            }
+
+            return Ok(self.availability());
+        }
+
+        if !tcp_header.syn() {
+            return Ok(self.availability());
+        }
+
+        self.recv.nxt = tcp_header.sequence_number().wrapping_add(1);
+
+        self.send.wnd = tcp_header.window_size() as u32;
+        self.send.wl1 = tcp_header.sequence_number();
+        self.send.wl2 = self.send.iss;
+
+        let options = Options::parse(&tcp_header);
+
+        self.negotiate(&options);
+
+        self.tcp_h.ack = true;
+
+        if ack_ok {
+            self.on_ack(sink, ackn, self.tsecr(&options))?;
+            self.state = State::Estab;
+            self.write(sink, self.send.nxt, 0)?;
+        } else {
+            // simultaneous open: answer with a SYN,ACK from our original ISS
+            self.state = State::SynRcvd;
+            self.tcp_h.syn = true;
+            self.write(sink, self.send.iss, 0)?;
+        }
+
+        Ok(self.availability())
+    }
+
+    pub(crate) fn on_packet(
+        &mut self,
+        sink: &mut dyn PacketSink,
+        tcp_header: etherparse::TcpHeaderSlice,
+        data: &[u8],
+    ) -> Result<Available> {
+        if let State::SynSent = self.state {
+            return self.on_syn_sent_packet(sink, tcp_header);
+        }
+
+        if let State::Closed = self.state {
+            // all that's left is for the stream to be dropped; to the peer the connection
+            // no longer exists (RFC 9293 3.10.7.1)
+            reset_unknown(sink, self.config.ttl, self.quad(), &tcp_header, data.len())?;
+            return Ok(self.availability());
+        }
+
+        let seqn = tcp_header.sequence_number();
+
+        let options = Options::parse(&tcp_header);
+
+        // resets are acceptable regardless of their timestamp (RFC 7323 5.2)
+        if self.timestamps.enabled && !tcp_header.rst() {
+            match options.timestamp {
+                // once negotiated, every segment has to carry one (RFC 7323 3.2)
+                None => return Ok(self.availability()),
+                Some((tsval, _))
+                    if wrapping_lt(tsval, self.timestamps.recent)
+                        && self.timestamps.recent_valid(self.now()) =>
+                {
+                    // an old duplicate from a previous trip around the sequence number
+                    // space (PAWS, RFC 7323 5.3)
+                    self.write(sink, self.send.nxt, 0)?;
+                    return Ok(self.availability());
+                }
+                _ => {}
+            }
+        }
+
+        let mut slen = data.len() as u32;
+
+        if tcp_header.fin() {
+            slen += 1
+        };
+
+        if tcp_header.syn() {
+            slen += 1
+        };
+
+        let wend = self.recv.nxt.wrapping_add(self.recv.wnd);
+
+        let okay = if slen == 0 {
+            // zero-length segment has separate rules for acceptance
+            if self.recv.wnd == 0 {
+                seqn == self.recv.nxt
+            } else {
+                is_between_wrapped(self.recv.nxt.wrapping_sub(1), seqn, wend)
+            }
+        } else {
+            self.recv.wnd != 0
+                && (is_between_wrapped(self.recv.nxt.wrapping_sub(1), seqn, wend)
+                    || is_between_wrapped(
+                        self.recv.nxt.wrapping_sub(1),
+                        seqn.wrapping_add(slen - 1),
+                        wend,
+                    ))
+        };
+
+        if !okay {
+            eprintln!("NOT OKAY");
+
+            // an out-of-window reset is dropped without a word (RFC 5961 3.2)
+            if tcp_header.rst() {
+                return Ok(self.availability());
+            }
+
+            if let State::SynRcvd = self.state {
+                // most likely a retransmitted SYN, so our SYN,ACK got lost
+                self.tcp_h.syn = true;
+                self.write(sink, self.send.iss, 0)?;
+            } else {
+                let end = seqn.wrapping_add(data.len() as u32);
+
+                if !data.is_empty() && !wrapping_lt(self.recv.nxt, end) {
+                    // we have all of that already, most likely our ACK got lost
+                    self.dsack = Some((seqn, end));
+                }
+
+                if matches!(self.state, State::TimeWait) && tcp_header.fin() {
+                    // the peer didn't get our last ACK; restart the 2 MSL timeout
+                    self.enter_time_wait();
+                }
+
+                self.write(sink, self.send.nxt, 0)?;
+            }
+
+            return Ok(self.availability());
+        }
+
+        self.keepalive.last_heard = self.now();
+        self.keepalive.probes = 0;
+
+        if let Some((tsval, _)) = options.timestamp {
+            // remember the timestamp to echo, but only from a segment that isn't beyond
+            // what we have acknowledged (RFC 7323 4.3)
+            if self.timestamps.enabled
+                && !wrapping_lt(tsval, self.timestamps.recent)
+                && !wrapping_lt(self.timestamps.last_ack_sent, seqn)
+            {
+                self.timestamps.recent = tsval;
+                self.timestamps.recent_at = self.now();
+            }
+        }
+
+        if tcp_header.rst() {
+            if seqn == self.recv.nxt {
+                self.reset();
+            } else {
+                // in-window but not exact: could be a blind reset attempt, so make the
+                // real peer prove itself with a challenge ACK (RFC 5961 3.2)
+                self.write(sink, self.send.nxt, 0)?;
+            }
+
+            return Ok(self.availability());
+        }
+
+        if tcp_header.syn() {
+            // a SYN inside the window of a synchronized connection gets a challenge ACK
+            // rather than a reset (RFC 5961 4.2)
+            self.write(sink, self.send.nxt, 0)?;
+            return Ok(self.availability());
+        }
+
+        if !tcp_header.ack() {
+            return Ok(self.availability());
+        }
+
+        let ackn = tcp_header.acknowledgment_number();
+
+        let wnd = (tcp_header.window_size() as u32) << self.send.wscale;
+
+        if let State::SynRcvd = self.state {
+            if is_between_wrapped(self.send.una, ackn, self.send.nxt.wrapping_add(1)) {
+                self.state = State::Estab;
+            } else {
+                send_rst(sink, self.config.ttl, self.quad(), ackn, None)?;
+                return Ok(self.availability());
+            }
+        }
+
+        if wrapping_lt(self.send.nxt, ackn) {
+            // acknowledges something we haven't sent yet
+            self.write(sink, self.send.nxt, 0)?;
+            return Ok(self.availability());
+        }
+
+        if let State::Estab
+        | State::FinWait1
+        | State::FinWait2
+        | State::CloseWait
+        | State::Closing
+        | State::LastAck = self.state
+        {
+            if self.sack {
+                for &(start, end) in &options.sack {
+                    self.scoreboard
+                        .add(self.send.una, self.send.nxt, start, end);
+                }
+            }
+
+            if is_between_wrapped(self.send.una, ackn, self.send.nxt.wrapping_add(1)) {
+                println!(
+                    "ack for {} (last: {}); {} bytes unacked",
+                    ackn,
+                    self.send.una,
+                    self.unacked.len()
+                );
+                if !self.unacked.is_empty() {
+                    let data_start = if self.send.una == self.send.iss {
+                        // send.una hasn't been updated yet with ACK for our SYN, so data starts just beyond it
+                        self.send.una.wrapping_add(1)
+                    } else {
+                        self.send.una
+                    };
+
+                    let acked_data_end =
+                        min(ackn.wrapping_sub(data_start) as _, self.unacked.len());
+
+                    self.unacked.drain(..acked_data_end);
+                }
+                self.on_ack(sink, ackn, self.tsecr(&options))?;
+            } else if ackn == self.send.una
+                && self.send.una != self.send.nxt
+                && data.is_empty()
+                && !tcp_header.fin()
+                && wnd == self.send.wnd
+            {
+                self.on_dup_ack(sink)?;
+            }
+
+            // take the peer's window from the most recent segment, not from a reordered
+            // older one (RFC 9293 3.10.7.4)
+            if wrapping_lt(self.send.wl1, seqn)
+                || (self.send.wl1 == seqn && !wrapping_lt(ackn, self.send.wl2))
+            {
+                self.send.wnd = wnd;
+                self.send.wl1 = seqn;
+                self.send.wl2 = ackn;
+            }
+        }
+
+        let fin_acked = self
+            .closed_at
+            .is_some_and(|closed_at| self.send.una == closed_at.wrapping_add(1));
+
+        if fin_acked {
+            // our FIN has been ACKed!
+            match self.state {
+                State::FinWait1 => {
                     self.state = State::FinWait2;
+                    self.timers.fin_wait2 = Some(self.now());
                 }
+                State::Closing => self.enter_time_wait(),
+                State::LastAck => self.state = State::Closed,
+                _ => {}
             }
         }
 
         if !data.is_empty() {
             if let State::Estab | State::FinWait1 | State::FinWait2 = self.state {
-                let mut unread_data_at = self.recv.nxt.wrapping_sub(seqn) as usize;
-                if unread_data_at > data.len() {
-                    // we must have received a re-transmitted FIN that we have already seen
-                    // nxt points to beyond the fin, but the fin is not in data!
-                    assert_eq!(unread_data_at, data.len() + 1);
-                    unread_data_at = 0;
-                }
-                self.incoming.extend(&data[unread_data_at..]);
+                // never take in more than the window has room for
+                let data = &data[..min(data.len(), wend.wrapping_sub(seqn) as usize)];
+
+                if wrapping_lt(self.recv.nxt, seqn) {
+                    // there's a gap in front of this segment, hold on to it until it's
+                    // filled, and let the peer know right away (RFC 5681 4.2)
+                    self.reassembly.insert(self.recv.nxt, seqn, data);
+                    self.write(sink, self.send.nxt, 0)?;
+                } else {
+                    let filling_gap = !self.reassembly.is_empty();
+
+                    let unread_data_at = min(self.recv.nxt.wrapping_sub(seqn) as usize, data.len());
+
+                    if unread_data_at > 0 {
+                        self.dsack = Some((seqn, self.recv.nxt));
+                    }
+
+                    self.incoming.extend(&data[unread_data_at..]);
 
-                /*
-                Once the TCP takes responsibility for the data it advances
-                RCV.NXT over the data accepted, and adjusts RCV.WND as
-                apporopriate to the current buffer availability.  The total of
-                RCV.NXT and RCV.WND should not be reduced.
-                 */
-                self.recv.nxt = seqn.wrapping_add(data.len() as u32);
+                    /*
+                    Once the TCP takes responsibility for the data it advances
+                    RCV.NXT over the data accepted, and adjusts RCV.WND as
+                    apporopriate to the current buffer availability.  The total of
+                    RCV.NXT and RCV.WND should not be reduced.
+                     */
+                    self.take_in(data.len() - unread_data_at);
 
-                // Send an acknowledgment of the form: <SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>
-                // TODO: maybe just tick to piggyback ack on data?
-                self.write(nic, self.send.nxt, 0)?;
+                    while let Some(queued) = self.reassembly.pop(self.recv.nxt) {
+                        self.incoming.extend(&queued);
+                        self.take_in(queued.len());
+                    }
+
+                    // a segment filling a gap or repeating old data is acknowledged right
+                    // away, and so is one the window had no room for
+                    if filling_gap || self.dsack.is_some() || unread_data_at == data.len() {
+                        self.write(sink, self.send.nxt, 0)?;
+                    } else {
+                        self.delay_ack(sink, data.len() - unread_data_at)?;
+                    }
+                }
             }
         }
 
         if tcp_header.fin() {
+            self.reassembly
+                .set_fin(seqn.wrapping_add(data.len() as u32));
+        }
+
+        // only a FIN that is next in sequence closes our receive side; one that arrived
+        // ahead of data we're still missing waits until the gap is filled
+        if self.reassembly.take_fin(self.recv.nxt) {
+            self.recv.nxt = self.recv.nxt.wrapping_add(1);
+
             match self.state {
-                State::FinWait2 => {
-                    self.recv.nxt = self.recv.nxt.wrapping_add(1);
-                    self.write(nic, self.send.nxt, 0)?;
-                    self.state = State::TimeWait;
-                }
-                _ => unimplemented!(),
+                State::SynRcvd | State::Estab => self.state = State::CloseWait,
+                // our FIN is still unacknowledged, otherwise we'd be in FinWait2
+                State::FinWait1 => self.state = State::Closing,
+                State::FinWait2 => self.enter_time_wait(),
+                _ => {}
             }
         }
 
+        if tcp_header.fin() {
+            self.write(sink, self.send.nxt, 0)?;
+        }
+
         Ok(self.availability())
     }
 }
@@ -462,15 +1834,99 @@ struct SendSequenceSpace {
     una: u32,
     /// sequence number of next byte to send
     nxt: u32,
-    /// the window size
-    wnd: u16,
+    /// the peer's window size
+    wnd: u32,
+    /// how far the window field of the peer's segments is shifted (RFC 7323)
+    wscale: u8,
+    /// segment sequence number used for the last window update
+    wl1: u32,
+    /// segment acknowledgment number used for the last window update
+    wl2: u32,
 }
 
 struct RecvSequenceSpace {
     /// The sequence number of the next byte to be recieved from the client
     nxt: u32,
-    /// The client's window size
-    wnd: u16,
+    /// The window we offer the client, i.e. how much it may send beyond `nxt`
+    wnd: u32,
+    /// How far we shift `wnd` right when putting it in a segment (RFC 7323)
+    wscale: u8,
+}
+
+/// Sends a bare RST from `quad.dest` to `quad.src`, outside of any connection state.
+fn send_rst(
+    sink: &mut dyn PacketSink,
+    ttl: u8,
+    quad: Quad,
+    seq: u32,
+    ack: Option<u32>,
+) -> Result<()> {
+    let mut tcp_h = etherparse::TcpHeader::new(quad.dest.1, quad.src.1, seq, 0);
+
+    tcp_h.rst = true;
+
+    if let Some(ack) = ack {
+        tcp_h.ack = true;
+        tcp_h.acknowledgment_number = ack;
+    }
+
+    let ip_h = etherparse::Ipv4Header::new(
+        tcp_h.header_len_u16(),
+        ttl,
+        etherparse::IpNumber::TCP,
+        quad.dest.0.octets(),
+        quad.src.0.octets(),
+    )
+    .expect("Failed to create IP header");
+
+    tcp_h.checksum = tcp_h
+        .calc_checksum_ipv4(&ip_h, &[])
+        .expect("failed to compute checksum");
+
+    let mut buf = Vec::with_capacity(ip_h.header_len() + tcp_h.header_len());
+
+    ip_h.write(&mut buf)?;
+    tcp_h.write(&mut buf)?;
+
+    sink.send(&buf)?;
+
+    Ok(())
+}
+
+/// Answers a segment that doesn't belong to any connection, as a CLOSED TCP would
+/// (RFC 9293 3.10.7.1). `quad` is oriented as seen by us, i.e. `src` is the peer.
+pub(crate) fn reset_unknown(
+    sink: &mut dyn PacketSink,
+    ttl: u8,
+    quad: Quad,
+    tcp_header: &etherparse::TcpHeaderSlice,
+    data_len: usize,
+) -> Result<()> {
+    if tcp_header.rst() {
+        return Ok(());
+    }
+
+    if tcp_header.ack() {
+        return send_rst(sink, ttl, quad, tcp_header.acknowledgment_number(), None);
+    }
+
+    let mut slen = data_len as u32;
+
+    if tcp_header.syn() {
+        slen += 1;
+    }
+
+    if tcp_header.fin() {
+        slen += 1;
+    }
+
+    send_rst(
+        sink,
+        ttl,
+        quad,
+        0,
+        Some(tcp_header.sequence_number().wrapping_add(slen)),
+    )
 }
 
 fn wrapping_lt(lhs: u32, rhs: u32) -> bool {
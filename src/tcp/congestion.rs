@@ -0,0 +1,588 @@
+use std::time::{Duration, Instant};
+
+/// A congestion control algorithm, driving the size of a connection's congestion window.
+///
+/// All window quantities are in bytes. The connection calls `set_mss` before it starts
+/// sending data, and then reports ACKs and losses as they happen. Loss recovery after a
+/// fast retransmit goes through `on_fast_retransmit`, any number of
+/// `on_recovery_dup_ack`/`on_partial_ack` calls and finally `on_recovery_exit`.
+pub trait CongestionControl: Send {
+    /// The congestion window, i.e. how many bytes may be in flight.
+    fn cwnd(&self) -> u32;
+
+    /// The slow start threshold.
+    fn ssthresh(&self) -> u32;
+
+    /// Sets the sender MSS and resets the window to its initial size.
+    fn set_mss(&mut self, mss: u32);
+
+    /// `acked` bytes of new data were cumulatively acknowledged outside of loss recovery.
+    fn on_ack(&mut self, acked: u32, rtt: Option<Duration>, now: Instant);
+
+    /// A loss was inferred from duplicate ACKs, and loss recovery starts.
+    /// `flight_size` is the amount of outstanding data.
+    fn on_fast_retransmit(&mut self, flight_size: u32, now: Instant);
+
+    /// Another duplicate ACK arrived during loss recovery.
+    fn on_recovery_dup_ack(&mut self);
+
+    /// An ACK during loss recovery covered `acked` new bytes but not everything that was
+    /// outstanding when recovery started. Returns whether recovery carries on.
+    fn on_partial_ack(&mut self, acked: u32) -> bool;
+
+    /// Loss recovery is over; `flight_size` is the amount of data still outstanding.
+    fn on_recovery_exit(&mut self, flight_size: u32);
+
+    /// The retransmission timer expired.
+    fn on_timeout(&mut self, flight_size: u32, now: Instant);
+}
+
+/// Window state and the RFC 5681/6582 arithmetic shared by all the algorithms below.
+#[derive(Clone, Debug)]
+struct Window {
+    mss: u32,
+    cwnd: u32,
+    ssthresh: u32,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        let mut w = Window {
+            mss: 0,
+            cwnd: 0,
+            ssthresh: u32::MAX,
+        };
+
+        w.set_mss(536);
+
+        w
+    }
+}
+
+impl Window {
+    fn set_mss(&mut self, mss: u32) {
+        self.mss = mss;
+
+        // RFC 5681 3.1
+        self.cwnd = match mss {
+            0..=1095 => 4 * mss,
+            1096..=2190 => 3 * mss,
+            _ => 2 * mss,
+        };
+    }
+
+    fn in_slow_start(&self) -> bool {
+        self.cwnd < self.ssthresh
+    }
+
+    /// RFC 5681 equation 2.
+    fn slow_start(&mut self, acked: u32) {
+        self.cwnd = self.cwnd.saturating_add(acked.min(self.mss));
+    }
+
+    /// RFC 5681 equation 4, with a floor of two segments.
+    fn halved(&self, flight_size: u32) -> u32 {
+        (flight_size / 2).max(2 * self.mss)
+    }
+
+    fn inflate(&mut self) {
+        self.cwnd = self.cwnd.saturating_add(self.mss);
+    }
+
+    /// RFC 6582 3.2 step 5: deflate by the amount acknowledged, then add back one segment.
+    fn deflate(&mut self, acked: u32) {
+        self.cwnd = self.cwnd.saturating_sub(acked);
+
+        if acked >= self.mss {
+            self.cwnd = self.cwnd.saturating_add(self.mss);
+        }
+
+        self.cwnd = self.cwnd.max(self.mss);
+    }
+
+    /// RFC 6582 3.2 step 3, first option.
+    fn full_ack(&mut self, flight_size: u32) {
+        self.cwnd = self.ssthresh.min(flight_size.max(self.mss) + self.mss);
+    }
+}
+
+/// Reno as specified in RFC 5681: recovery ends on the first ACK for new data.
+#[derive(Clone, Debug, Default)]
+pub struct Reno {
+    w: Window,
+}
+
+impl CongestionControl for Reno {
+    fn cwnd(&self) -> u32 {
+        self.w.cwnd
+    }
+
+    fn ssthresh(&self) -> u32 {
+        self.w.ssthresh
+    }
+
+    fn set_mss(&mut self, mss: u32) {
+        self.w.set_mss(mss);
+    }
+
+    fn on_ack(&mut self, acked: u32, _rtt: Option<Duration>, _now: Instant) {
+        if self.w.in_slow_start() {
+            self.w.slow_start(acked);
+        } else {
+            // RFC 5681 equation 3
+            let mss = self.w.mss;
+            self.w.cwnd = self.w.cwnd.saturating_add((mss * mss / self.w.cwnd).max(1));
+        }
+    }
+
+    fn on_fast_retransmit(&mut self, flight_size: u32, _now: Instant) {
+        self.w.ssthresh = self.w.halved(flight_size);
+        self.w.cwnd = self.w.ssthresh + 3 * self.w.mss;
+    }
+
+    fn on_recovery_dup_ack(&mut self) {
+        self.w.inflate();
+    }
+
+    fn on_partial_ack(&mut self, _acked: u32) -> bool {
+        false
+    }
+
+    fn on_recovery_exit(&mut self, _flight_size: u32) {
+        self.w.cwnd = self.w.ssthresh;
+    }
+
+    fn on_timeout(&mut self, flight_size: u32, _now: Instant) {
+        self.w.ssthresh = self.w.halved(flight_size);
+        self.w.cwnd = self.w.mss;
+    }
+}
+
+/// NewReno (RFC 6582): Reno, but partial ACKs keep the connection in fast recovery so
+/// several losses in one window are repaired without waiting for a timeout.
+#[derive(Clone, Debug, Default)]
+pub struct NewReno {
+    reno: Reno,
+}
+
+impl CongestionControl for NewReno {
+    fn cwnd(&self) -> u32 {
+        self.reno.cwnd()
+    }
+
+    fn ssthresh(&self) -> u32 {
+        self.reno.ssthresh()
+    }
+
+    fn set_mss(&mut self, mss: u32) {
+        self.reno.set_mss(mss);
+    }
+
+    fn on_ack(&mut self, acked: u32, rtt: Option<Duration>, now: Instant) {
+        self.reno.on_ack(acked, rtt, now);
+    }
+
+    fn on_fast_retransmit(&mut self, flight_size: u32, now: Instant) {
+        self.reno.on_fast_retransmit(flight_size, now);
+    }
+
+    fn on_recovery_dup_ack(&mut self) {
+        self.reno.on_recovery_dup_ack();
+    }
+
+    fn on_partial_ack(&mut self, acked: u32) -> bool {
+        self.reno.w.deflate(acked);
+        true
+    }
+
+    fn on_recovery_exit(&mut self, flight_size: u32) {
+        self.reno.w.full_ack(flight_size);
+    }
+
+    fn on_timeout(&mut self, flight_size: u32, now: Instant) {
+        self.reno.on_timeout(flight_size, now);
+    }
+}
+
+/// CUBIC (RFC 9438), with NewReno-style loss recovery.
+#[derive(Clone, Debug, Default)]
+pub struct Cubic {
+    w: Window,
+    /// Window, in segments, right before the last reduction.
+    w_max: f64,
+    /// Start of the current congestion avoidance epoch, `None` until it begins.
+    epoch: Option<Instant>,
+    /// Time it takes the cubic function to grow back to `w_max`, in seconds.
+    k: f64,
+    /// Estimate of what Reno's window would be, in segments (the "Reno-friendly" region).
+    w_est: f64,
+}
+
+impl Cubic {
+    const C: f64 = 0.4;
+    const BETA: f64 = 0.7;
+
+    /// RTT to assume before we have a measurement.
+    const DEFAULT_RTT: Duration = Duration::from_millis(100);
+
+    fn segments(&self, bytes: u32) -> f64 {
+        bytes as f64 / self.w.mss as f64
+    }
+
+    fn w_cubic(&self, t: f64) -> f64 {
+        Self::C * (t - self.k).powi(3) + self.w_max
+    }
+
+    /// Remembers where the window was and reduces ssthresh (RFC 9438 4.6 and 4.7).
+    fn reduce(&mut self) {
+        let cwnd = self.segments(self.w.cwnd);
+
+        // fast convergence: release bandwidth if we didn't even get back to the last w_max
+        self.w_max = if cwnd < self.w_max {
+            cwnd * (1.0 + Self::BETA) / 2.0
+        } else {
+            cwnd
+        };
+
+        self.epoch = None;
+
+        self.w.ssthresh = ((self.w.cwnd as f64 * Self::BETA) as u32).max(2 * self.w.mss);
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn cwnd(&self) -> u32 {
+        self.w.cwnd
+    }
+
+    fn ssthresh(&self) -> u32 {
+        self.w.ssthresh
+    }
+
+    fn set_mss(&mut self, mss: u32) {
+        self.w.set_mss(mss);
+    }
+
+    fn on_ack(&mut self, acked: u32, rtt: Option<Duration>, now: Instant) {
+        if self.w.in_slow_start() {
+            self.w.slow_start(acked);
+            return;
+        }
+
+        let cwnd = self.segments(self.w.cwnd);
+
+        let epoch = match self.epoch {
+            Some(epoch) => epoch,
+            None => {
+                self.k = if cwnd < self.w_max {
+                    ((self.w_max - cwnd) / Self::C).cbrt()
+                } else {
+                    self.w_max = cwnd;
+                    0.0
+                };
+
+                self.w_est = cwnd;
+                self.epoch = Some(now);
+
+                now
+            }
+        };
+
+        let t = now.duration_since(epoch).as_secs_f64();
+
+        let rtt = rtt.unwrap_or(Self::DEFAULT_RTT).as_secs_f64();
+
+        let alpha = 3.0 * (1.0 - Self::BETA) / (1.0 + Self::BETA);
+
+        self.w_est += alpha * self.segments(acked) / cwnd;
+
+        let next = if self.w_cubic(t) < self.w_est {
+            self.w_est
+        } else {
+            let target = self.w_cubic(t + rtt).clamp(cwnd, 1.5 * cwnd);
+            cwnd + (target - cwnd) / cwnd * self.segments(acked)
+        };
+
+        self.w.cwnd = ((next * self.w.mss as f64) as u32).max(self.w.cwnd);
+    }
+
+    fn on_fast_retransmit(&mut self, _flight_size: u32, _now: Instant) {
+        self.reduce();
+        self.w.cwnd = self.w.ssthresh + 3 * self.w.mss;
+    }
+
+    fn on_recovery_dup_ack(&mut self) {
+        self.w.inflate();
+    }
+
+    fn on_partial_ack(&mut self, acked: u32) -> bool {
+        self.w.deflate(acked);
+        true
+    }
+
+    fn on_recovery_exit(&mut self, flight_size: u32) {
+        self.w.full_ack(flight_size);
+    }
+
+    fn on_timeout(&mut self, _flight_size: u32, _now: Instant) {
+        self.reduce();
+        self.w.cwnd = self.w.mss;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: u32 = 1000;
+
+    const RTT: Option<Duration> = Some(Duration::from_millis(100));
+
+    fn window(cwnd: u32, ssthresh: u32) -> Window {
+        Window {
+            mss: MSS,
+            cwnd,
+            ssthresh,
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn initial_window() {
+        for (mss, cwnd) in [
+            (536, 2144),
+            (1095, 4380),
+            (1096, 3288),
+            (1460, 4380),
+            (2190, 6570),
+            (2191, 4382),
+            (9000, 18000),
+        ] {
+            let mut reno = Reno::default();
+
+            reno.set_mss(mss);
+
+            assert_eq!(reno.cwnd(), cwnd, "mss {mss}");
+        }
+    }
+
+    #[test]
+    fn reno_growth() {
+        // (cwnd, ssthresh, acked, cwnd after)
+        for (cwnd, ssthresh, acked, after) in [
+            // slow start, by at most one MSS per ACK
+            (4000, u32::MAX, 500, 4500),
+            (4000, u32::MAX, 3000, 5000),
+            (4000, 8000, 1000, 5000),
+            // congestion avoidance, by MSS*MSS/cwnd but at least a byte
+            (10_000, 5000, 1000, 10_100),
+            (10_000, 10_000, 1000, 10_100),
+            (2_000_000, 5000, 1000, 2_000_001),
+        ] {
+            let mut reno = Reno {
+                w: window(cwnd, ssthresh),
+            };
+
+            reno.on_ack(acked, RTT, Instant::now());
+
+            assert_eq!(reno.cwnd(), after, "cwnd {cwnd} ssthresh {ssthresh}");
+        }
+    }
+
+    #[test]
+    fn reno_reductions() {
+        // (flight size, ssthresh, cwnd after a fast retransmit)
+        for (flight_size, ssthresh, cwnd) in [
+            (20_000, 10_000, 13_000),
+            (5000, 2500, 5500),
+            // never below two segments
+            (3000, 2000, 5000),
+            (0, 2000, 5000),
+        ] {
+            let mut reno = Reno {
+                w: window(30_000, u32::MAX),
+            };
+
+            reno.on_fast_retransmit(flight_size, Instant::now());
+
+            assert_eq!(reno.ssthresh(), ssthresh, "flight size {flight_size}");
+            assert_eq!(reno.cwnd(), cwnd, "flight size {flight_size}");
+
+            reno.on_recovery_dup_ack();
+
+            assert_eq!(reno.cwnd(), cwnd + MSS);
+
+            reno.on_recovery_exit(flight_size);
+
+            assert_eq!(reno.cwnd(), ssthresh);
+
+            reno.on_timeout(flight_size, Instant::now());
+
+            assert_eq!(reno.ssthresh(), ssthresh);
+            assert_eq!(reno.cwnd(), MSS);
+        }
+    }
+
+    #[test]
+    fn reno_leaves_recovery_on_a_partial_ack() {
+        let mut reno = Reno {
+            w: window(13_000, 10_000),
+        };
+
+        assert!(!reno.on_partial_ack(3000));
+        assert_eq!(reno.cwnd(), 13_000);
+    }
+
+    #[test]
+    fn newreno_partial_ack_deflation() {
+        // (cwnd, acked, cwnd after)
+        for (cwnd, acked, after) in [
+            // deflated by what was acknowledged, plus a segment back
+            (13_000, 3000, 11_000),
+            (13_000, 1000, 13_000),
+            // less than a segment acknowledged: nothing added back
+            (13_000, 500, 12_500),
+            // never below one segment
+            (1200, 1100, 1100),
+            (1500, 5000, 1000),
+            (900, 500, 1000),
+        ] {
+            let mut newreno = NewReno {
+                reno: Reno {
+                    w: window(cwnd, 10_000),
+                },
+            };
+
+            assert!(newreno.on_partial_ack(acked));
+            assert_eq!(newreno.cwnd(), after, "cwnd {cwnd} acked {acked}");
+        }
+    }
+
+    #[test]
+    fn newreno_full_ack() {
+        // (flight size, cwnd after recovery)
+        for (flight_size, cwnd) in [
+            // flight size plus a segment...
+            (4000, 5000),
+            (0, 2000),
+            (500, 2000),
+            // ...but no more than ssthresh
+            (9000, 10_000),
+            (20_000, 10_000),
+        ] {
+            let mut newreno = NewReno {
+                reno: Reno {
+                    w: window(13_000, 10_000),
+                },
+            };
+
+            newreno.on_recovery_exit(flight_size);
+
+            assert_eq!(newreno.cwnd(), cwnd, "flight size {flight_size}");
+        }
+    }
+
+    fn cubic(cwnd: u32, ssthresh: u32) -> Cubic {
+        Cubic {
+            w: window(cwnd, ssthresh),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cubic_reduction() {
+        // (cwnd in segments, w_max, w_max after, ssthresh after)
+        for (cwnd, w_max, w_max_after, ssthresh) in [
+            (100, 0.0, 100.0, 70_000),
+            (100, 100.0, 100.0, 70_000),
+            // fast convergence: short of the last w_max, so back off further
+            (80, 100.0, 68.0, 56_000),
+            // never below two segments
+            (2, 0.0, 2.0, 2000),
+        ] {
+            let mut c = Cubic {
+                w_max,
+                epoch: Some(Instant::now()),
+                ..cubic(cwnd * MSS, u32::MAX)
+            };
+
+            c.on_fast_retransmit(cwnd * MSS, Instant::now());
+
+            assert!(close(c.w_max, w_max_after), "cwnd {cwnd}: {}", c.w_max);
+            assert_eq!(c.ssthresh(), ssthresh, "cwnd {cwnd}");
+            assert_eq!(c.cwnd(), ssthresh + 3 * MSS);
+            assert_eq!(c.epoch, None);
+        }
+    }
+
+    #[test]
+    fn cubic_epoch_start() {
+        // (w_max, cwnd in segments, K, w_max after)
+        for (w_max, cwnd, k, w_max_after) in [
+            // K = cbrt((w_max - cwnd) / C)
+            (100.0, 70, 75f64.cbrt(), 100.0),
+            (70.4, 70, 1.0, 70.4),
+            // already at or past w_max: plateau right away, around the current window
+            (70.0, 70, 0.0, 70.0),
+            (50.0, 70, 0.0, 70.0),
+        ] {
+            let mut c = Cubic {
+                w_max,
+                ..cubic(cwnd * MSS, cwnd * MSS)
+            };
+
+            let now = Instant::now();
+
+            c.on_ack(MSS, RTT, now);
+
+            assert!(close(c.k, k), "w_max {w_max}: K {}", c.k);
+            assert!(close(c.w_max, w_max_after), "w_max {w_max}");
+            assert_eq!(c.epoch, Some(now));
+        }
+    }
+
+    #[test]
+    fn cubic_growth() {
+        // (seconds into the epoch, w_max, K, w_est, cwnd after) for a 20 segment window
+        // and an ACK for one segment
+        for (t, w_max, k, w_est, after) in [
+            // W_cubic(t + RTT) is far ahead: grow by at most half a segment per segment
+            (10.0, 10.0, 0.0, 0.0, 20_500),
+            // W_cubic(2.1) = 23.7044, so grow by 3.7044 / 20 segments
+            (2.0, 20.0, 0.0, 0.0, 20_185),
+            // W_cubic(t + RTT) is behind: hold the window
+            (0.0, 10.0, 0.0, 0.0, 20_000),
+            // Reno would be ahead of W_cubic(t): follow W_est instead, which grows by
+            // alpha / cwnd = 0.0265 segments
+            (0.0, 10.0, 10.0, 30.0, 30_026),
+        ] {
+            let start = Instant::now();
+
+            let mut c = Cubic {
+                w_max,
+                epoch: Some(start),
+                k,
+                w_est,
+                ..cubic(20 * MSS, 10 * MSS)
+            };
+
+            c.on_ack(MSS, RTT, start + Duration::from_secs_f64(t));
+
+            assert_eq!(c.cwnd(), after, "t {t} w_max {w_max}");
+        }
+    }
+
+    #[test]
+    fn cubic_timeout() {
+        let mut c = cubic(100 * MSS, u32::MAX);
+
+        c.on_timeout(100 * MSS, Instant::now());
+
+        assert_eq!(c.cwnd(), MSS);
+        assert_eq!(c.ssthresh(), 70_000);
+        assert!(close(c.w_max, 100.0));
+    }
+}
@@ -0,0 +1,45 @@
+use etherparse::{TcpHeaderSlice, TcpOptionElement};
+
+/// MSS to assume when the peer doesn't send the option (RFC 9293 3.7.1).
+pub(crate) const DEFAULT_MSS: u16 = 536;
+
+/// The options we understand, as found on an incoming segment.
+#[derive(Debug, Default)]
+pub(crate) struct Options {
+    pub(crate) mss: Option<u16>,
+    pub(crate) wscale: Option<u8>,
+    /// `TSval` and `TSecr`.
+    pub(crate) timestamp: Option<(u32, u32)>,
+    pub(crate) sack_permitted: bool,
+    /// SACK blocks as `[start, end)` ranges.
+    pub(crate) sack: Vec<(u32, u32)>,
+}
+
+impl Options {
+    /// Picks the options out of `tcp_header`. Unknown options are skipped, and parsing
+    /// stops at the first malformed one.
+    pub(crate) fn parse(tcp_header: &TcpHeaderSlice) -> Self {
+        let mut options = Options::default();
+
+        for option in tcp_header.options_iterator() {
+            match option {
+                Ok(TcpOptionElement::MaximumSegmentSize(mss)) => options.mss = Some(mss),
+                Ok(TcpOptionElement::WindowScale(shift)) => options.wscale = Some(shift),
+                Ok(TcpOptionElement::Timestamp(tsval, tsecr)) => {
+                    options.timestamp = Some((tsval, tsecr))
+                }
+                Ok(TcpOptionElement::SelectiveAcknowledgementPermitted) => {
+                    options.sack_permitted = true
+                }
+                Ok(TcpOptionElement::SelectiveAcknowledgement(first, rest)) => {
+                    options.sack.push(first);
+                    options.sack.extend(rest.into_iter().flatten());
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+
+        options
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::VecDeque;
+
+/// Segments that arrived ahead of RCV.NXT, held until the gap in front of them fills.
+///
+/// Blocks are kept sorted, non-overlapping and coalesced. All positions are compared
+/// relative to RCV.NXT, so sequence number wraparound doesn't matter.
+pub(crate) struct Reassembly {
+    /// Starting sequence number and contents of each block.
+    blocks: VecDeque<(u32, Vec<u8>)>,
+    /// Total bytes held in `blocks`.
+    buffered: usize,
+    /// Upper bound for `buffered`; anything beyond it is dropped and has to be resent.
+    limit: usize,
+    /// Sequence number of a FIN that arrived ahead of some of the data.
+    fin: Option<u32>,
+    /// Where the last few segments that went in here started, most recent first.
+    recent: VecDeque<u32>,
+}
+
+/// How many SACK blocks fit in a segment at most.
+const MAX_SACK_BLOCKS: usize = 4;
+
+impl Reassembly {
+    pub(crate) fn new(limit: usize) -> Self {
+        Reassembly {
+            blocks: Default::default(),
+            buffered: 0,
+            limit,
+            fin: None,
+            recent: Default::default(),
+        }
+    }
+
+    /// Buffers `data`, which starts at `seq` somewhere after `nxt`. Bytes we already hold
+    /// are not stored twice.
+    pub(crate) fn insert(&mut self, nxt: u32, seq: u32, data: &[u8]) {
+        self.trim(nxt);
+
+        self.recent.retain(|&recent| recent != seq);
+        self.recent.push_front(seq);
+        self.recent.truncate(MAX_SACK_BLOCKS);
+
+        let rel = |seq: u32| seq.wrapping_sub(nxt) as usize;
+
+        let start = rel(seq);
+        let end = start + data.len();
+
+        // the parts of [start, end) not covered by any block yet
+        let mut pieces = Vec::new();
+        let mut cursor = start;
+
+        for (bseq, bdata) in &self.blocks {
+            let bstart = rel(*bseq);
+            let bend = bstart + bdata.len();
+
+            if bend <= cursor {
+                continue;
+            }
+
+            if bstart >= end {
+                break;
+            }
+
+            if bstart > cursor {
+                pieces.push((cursor, bstart));
+            }
+
+            cursor = cursor.max(bend);
+        }
+
+        if cursor < end {
+            pieces.push((cursor, end));
+        }
+
+        for (pstart, pend) in pieces {
+            let pend = pend.min(pstart + (self.limit - self.buffered));
+
+            if pend <= pstart {
+                break;
+            }
+
+            let at = self
+                .blocks
+                .iter()
+                .position(|(bseq, _)| rel(*bseq) > pstart)
+                .unwrap_or(self.blocks.len());
+
+            let bseq = nxt.wrapping_add(pstart as u32);
+
+            self.blocks
+                .insert(at, (bseq, data[pstart - start..pend - start].to_vec()));
+
+            self.buffered += pend - pstart;
+        }
+
+        self.coalesce();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Removes and returns the data that now directly follows `nxt`, if any.
+    pub(crate) fn pop(&mut self, nxt: u32) -> Option<Vec<u8>> {
+        self.trim(nxt);
+
+        if self.blocks.front()?.0 != nxt {
+            return None;
+        }
+
+        let (_, data) = self.blocks.pop_front()?;
+
+        self.buffered -= data.len();
+
+        Some(data)
+    }
+
+    /// SACK blocks for what we hold: first the block with the segment that arrived last,
+    /// then those with the other recent arrivals, then the rest (RFC 2018 4).
+    pub(crate) fn sack_blocks(&self) -> Vec<(u32, u32)> {
+        let mut blocks: Vec<(u32, u32)> = Vec::new();
+
+        let ranges = self
+            .blocks
+            .iter()
+            .map(|(bseq, bdata)| (*bseq, bseq.wrapping_add(bdata.len() as u32)));
+
+        let recent = self.recent.iter().filter_map(|&seq| {
+            ranges
+                .clone()
+                .find(|&(start, end)| seq.wrapping_sub(start) < end.wrapping_sub(start))
+        });
+
+        for block in recent.chain(ranges.clone()) {
+            if !blocks.contains(&block) {
+                blocks.push(block);
+            }
+        }
+
+        blocks
+    }
+
+    /// Remembers a FIN at `seq`.
+    pub(crate) fn set_fin(&mut self, seq: u32) {
+        self.fin = Some(seq);
+    }
+
+    /// Whether the FIN is the next thing after `nxt`; it is forgotten if so.
+    pub(crate) fn take_fin(&mut self, nxt: u32) -> bool {
+        if self.fin == Some(nxt) {
+            self.fin = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Drops whatever `nxt` has already moved past.
+    fn trim(&mut self, nxt: u32) {
+        while let Some((bseq, bdata)) = self.blocks.front_mut() {
+            let behind = nxt.wrapping_sub(*bseq) as usize;
+
+            // blocks are never more than a window ahead, so anything further is behind us
+            if behind == 0 || behind > (1 << 31) {
+                break;
+            }
+
+            if behind >= bdata.len() {
+                self.buffered -= bdata.len();
+                self.blocks.pop_front();
+            } else {
+                bdata.drain(..behind);
+                *bseq = nxt;
+                self.buffered -= behind;
+                break;
+            }
+        }
+    }
+
+    fn coalesce(&mut self) {
+        let mut merged: VecDeque<(u32, Vec<u8>)> = VecDeque::with_capacity(self.blocks.len());
+
+        for (bseq, bdata) in self.blocks.drain(..) {
+            match merged.back_mut() {
+                Some((pseq, pdata)) if pseq.wrapping_add(pdata.len() as u32) == bseq => {
+                    pdata.extend_from_slice(&bdata);
+                }
+                _ => merged.push_back((bseq, bdata)),
+            }
+        }
+
+        self.blocks = merged;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The blocks held, as `(seq, contents)`.
+    fn blocks(r: &Reassembly) -> Vec<(u32, &[u8])> {
+        r.blocks
+            .iter()
+            .map(|(seq, data)| (*seq, data.as_slice()))
+            .collect()
+    }
+
+    #[test]
+    fn overlapping_segments_are_stored_once() {
+        let mut r = Reassembly::new(1024);
+
+        r.insert(100, 110, b"abcd");
+        r.insert(100, 120, b"klmn");
+
+        // covers the gap and overlaps both neighbours, with different bytes where they
+        // overlap; what we had first wins
+        r.insert(100, 112, b"XXefghijXX");
+
+        assert_eq!(blocks(&r), [(110, &b"abcdefghijklmn"[..])]);
+        assert_eq!(r.buffered, 14);
+    }
+
+    #[test]
+    fn data_behind_nxt_is_trimmed() {
+        let mut r = Reassembly::new(1024);
+
+        r.insert(100, 110, b"abcdef");
+
+        assert_eq!(r.pop(100), None);
+        assert_eq!(r.pop(113), Some(b"def".to_vec()));
+        assert!(r.blocks.is_empty());
+        assert_eq!(r.buffered, 0);
+    }
+
+    #[test]
+    fn wraps_around_the_sequence_space() {
+        let mut r = Reassembly::new(1024);
+
+        let nxt = u32::MAX - 5;
+
+        r.insert(nxt, nxt.wrapping_add(8), b"later");
+        r.insert(nxt, nxt.wrapping_add(2), b"before");
+
+        assert_eq!(blocks(&r), [(nxt.wrapping_add(2), &b"beforelater"[..])]);
+    }
+
+    #[test]
+    fn stops_at_the_memory_limit() {
+        let mut r = Reassembly::new(8);
+
+        r.insert(0, 10, b"abcdef");
+        r.insert(0, 20, b"ghijkl");
+
+        // only the first two bytes of the second segment fit
+        assert_eq!(blocks(&r), [(10, &b"abcdef"[..]), (20, &b"gh"[..])]);
+        assert_eq!(r.buffered, 8);
+
+        r.insert(0, 30, b"full");
+
+        assert_eq!(r.blocks.len(), 2);
+
+        // handing data out makes room again
+        assert_eq!(r.pop(10), Some(b"abcdef".to_vec()));
+
+        r.insert(10, 30, b"room");
+
+        assert_eq!(r.buffered, 6);
+    }
+
+    #[test]
+    fn sack_blocks_start_with_the_latest_arrival() {
+        let mut r = Reassembly::new(1024);
+
+        r.insert(0, 10, b"aa");
+        r.insert(0, 20, b"bb");
+        r.insert(0, 30, b"cc");
+        r.insert(0, 12, b"aa");
+
+        assert_eq!(r.sack_blocks(), [(10, 14), (30, 32), (20, 22)]);
+    }
+}
@@ -0,0 +1,215 @@
+use super::{wrapping_lt, DUP_ACK_THRESHOLD};
+
+/// What the peer has told us through SACK blocks that it holds beyond SND.UNA (RFC 2018),
+/// along with the RFC 6675 rules for telling what must have been lost.
+pub(crate) struct Scoreboard {
+    /// SACKed ranges `[start, end)`, sorted, disjoint and coalesced.
+    ranges: Vec<(u32, u32)>,
+}
+
+impl Scoreboard {
+    pub(crate) fn new() -> Self {
+        Scoreboard { ranges: Vec::new() }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.ranges.clear();
+    }
+
+    /// Records that the peer holds `[start, end)`. Blocks that aren't wholly inside
+    /// `[una, nxt)` are ignored; that includes DSACK blocks, which report data below `una`.
+    pub(crate) fn add(&mut self, una: u32, nxt: u32, start: u32, end: u32) {
+        let rel = |seq: u32| seq.wrapping_sub(una);
+
+        if rel(start) >= rel(end) || rel(end) > rel(nxt) {
+            return;
+        }
+
+        self.ranges.push((start, end));
+        self.ranges.sort_by_key(|&(start, _)| rel(start));
+
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(self.ranges.len());
+
+        for (start, end) in self.ranges.drain(..) {
+            match merged.last_mut() {
+                Some((_, pend)) if !wrapping_lt(*pend, start) => {
+                    if wrapping_lt(*pend, end) {
+                        *pend = end;
+                    }
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+
+        self.ranges = merged;
+    }
+
+    /// Forgets about everything below `una`, which the peer has acknowledged cumulatively.
+    pub(crate) fn trim(&mut self, una: u32) {
+        self.ranges.retain_mut(|(start, end)| {
+            if wrapping_lt(*start, una) {
+                *start = una;
+            }
+
+            wrapping_lt(una, *end)
+        });
+    }
+
+    /// The end of the highest SACKed range.
+    pub(crate) fn high(&self) -> Option<u32> {
+        self.ranges.last().map(|&(_, end)| end)
+    }
+
+    /// The first sequence number from `seq` on that hasn't been SACKed.
+    pub(crate) fn next_unsacked(&self, mut seq: u32) -> u32 {
+        for &(start, end) in &self.ranges {
+            if !wrapping_lt(seq, start) && wrapping_lt(seq, end) {
+                seq = end;
+            }
+        }
+
+        seq
+    }
+
+    /// Where the first SACKed range after `seq` starts.
+    pub(crate) fn next_sacked(&self, seq: u32) -> Option<u32> {
+        self.ranges
+            .iter()
+            .map(|&(start, _)| start)
+            .find(|&start| wrapping_lt(seq, start))
+    }
+
+    /// IsLost() of RFC 6675: whether so much has been SACKed above `seq` that the segment
+    /// there can't merely be delayed.
+    pub(crate) fn is_lost(&self, seq: u32, mss: u32) -> bool {
+        let mut ranges = 0;
+        let mut sacked = 0;
+
+        for &(start, end) in &self.ranges {
+            if wrapping_lt(seq, end) {
+                let start = if wrapping_lt(seq, start) { start } else { seq };
+
+                ranges += 1;
+                sacked += end.wrapping_sub(start);
+            }
+        }
+
+        ranges >= DUP_ACK_THRESHOLD || sacked > (DUP_ACK_THRESHOLD - 1) * mss
+    }
+
+    /// The ranges in `[una, nxt)` that haven't been SACKed.
+    pub(crate) fn holes(&self, una: u32, nxt: u32) -> Vec<(u32, u32)> {
+        let mut holes = Vec::new();
+        let mut cursor = una;
+
+        for &(start, end) in &self.ranges {
+            if wrapping_lt(cursor, start) {
+                holes.push((cursor, start));
+            }
+
+            cursor = end;
+        }
+
+        if wrapping_lt(cursor, nxt) {
+            holes.push((cursor, nxt));
+        }
+
+        holes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: u32 = 100;
+
+    #[test]
+    fn blocks_are_merged() {
+        let mut s = Scoreboard::new();
+
+        s.add(0, 1000, 300, 400);
+        s.add(0, 1000, 100, 200);
+        s.add(0, 1000, 150, 300);
+        s.add(0, 1000, 600, 700);
+
+        assert_eq!(s.ranges, [(100, 400), (600, 700)]);
+        assert_eq!(s.high(), Some(700));
+    }
+
+    #[test]
+    fn blocks_outside_the_window_are_ignored() {
+        let mut s = Scoreboard::new();
+
+        // a DSACK below SND.UNA, one beyond SND.NXT, and an empty one
+        s.add(500, 1000, 400, 450);
+        s.add(500, 1000, 900, 1100);
+        s.add(500, 1000, 700, 700);
+
+        assert!(s.ranges.is_empty());
+    }
+
+    #[test]
+    fn merges_across_wraparound() {
+        let mut s = Scoreboard::new();
+
+        let una = u32::MAX - 100;
+
+        s.add(una, 200, 50, 100);
+        s.add(una, 200, u32::MAX - 50, 50);
+
+        assert_eq!(s.ranges, [(u32::MAX - 50, 100)]);
+    }
+
+    #[test]
+    fn trimming_drops_what_was_acknowledged() {
+        let mut s = Scoreboard::new();
+
+        s.add(0, 1000, 100, 200);
+        s.add(0, 1000, 300, 400);
+
+        s.trim(350);
+
+        assert_eq!(s.ranges, [(350, 400)]);
+    }
+
+    #[test]
+    fn holes_lie_between_the_blocks() {
+        let mut s = Scoreboard::new();
+
+        s.add(0, 1000, 100, 200);
+        s.add(0, 1000, 300, 400);
+
+        assert_eq!(s.holes(0, 1000), [(0, 100), (200, 300), (400, 1000)]);
+        assert_eq!(s.next_unsacked(100), 200);
+        assert_eq!(s.next_sacked(200), Some(300));
+    }
+
+    #[test]
+    fn lost_after_three_blocks_above() {
+        let mut s = Scoreboard::new();
+
+        s.add(0, 1000, 100, 110);
+        s.add(0, 1000, 200, 210);
+
+        assert!(!s.is_lost(0, MSS));
+
+        s.add(0, 1000, 300, 310);
+
+        assert!(s.is_lost(0, MSS));
+        assert!(!s.is_lost(150, MSS));
+    }
+
+    #[test]
+    fn lost_after_enough_bytes_above() {
+        let mut s = Scoreboard::new();
+
+        s.add(0, 1000, 100, 300);
+
+        assert!(!s.is_lost(0, MSS));
+
+        s.add(0, 1000, 300, 301);
+
+        assert!(s.is_lost(0, MSS));
+    }
+}
@@ -0,0 +1,508 @@
+//! Drives single connections through the state machine, with a `Vec` standing in for the
+//! device and a manual clock for the timers.
+
+use etherparse::{Ipv4Header, Ipv4HeaderSlice, TcpHeader, TcpHeaderSlice, TcpOptionElement};
+
+use super::*;
+use crate::clock::ManualClock;
+
+const PEER: (Ipv4Addr, u16) = (Ipv4Addr::new(192, 168, 0, 1), 40000);
+const LOCAL: (Ipv4Addr, u16) = (Ipv4Addr::new(192, 168, 0, 2), 9000);
+
+const PEER_ISS: u32 = 1000;
+const ISS: u32 = 5000;
+
+/// Collects what a connection sends.
+#[derive(Default)]
+struct Sink(Vec<Vec<u8>>);
+
+impl PacketSink for Sink {
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        self.0.push(packet.to_vec());
+        Ok(())
+    }
+}
+
+impl Sink {
+    /// The TCP headers and payload lengths of what was sent since the last call.
+    fn take(&mut self) -> Vec<(TcpHeader, usize)> {
+        self.0
+            .drain(..)
+            .map(|packet| {
+                let ip = Ipv4HeaderSlice::from_slice(&packet).unwrap();
+                let tcp = TcpHeaderSlice::from_slice(&packet[ip.slice().len()..]).unwrap();
+
+                let len = packet.len() - ip.slice().len() - tcp.slice().len();
+
+                (tcp.to_header(), len)
+            })
+            .collect()
+    }
+}
+
+/// A segment from the peer, as an IP packet.
+fn packet(
+    seq: u32,
+    ack: Option<u32>,
+    flags: impl FnOnce(&mut TcpHeader),
+    options: &[TcpOptionElement],
+    data: &[u8],
+) -> Vec<u8> {
+    let mut tcp = TcpHeader::new(PEER.1, LOCAL.1, seq, 64240);
+
+    if let Some(ack) = ack {
+        tcp.ack = true;
+        tcp.acknowledgment_number = ack;
+    }
+
+    flags(&mut tcp);
+
+    tcp.set_options(options).unwrap();
+
+    let ip = Ipv4Header::new(
+        (tcp.header_len() + data.len()) as u16,
+        64,
+        etherparse::IpNumber::TCP,
+        PEER.0.octets(),
+        LOCAL.0.octets(),
+    )
+    .unwrap();
+
+    tcp.checksum = tcp.calc_checksum_ipv4(&ip, data).unwrap();
+
+    let mut packet = Vec::new();
+
+    ip.write(&mut packet).unwrap();
+    tcp.write(&mut packet).unwrap();
+    packet.extend_from_slice(data);
+
+    packet
+}
+
+fn deliver(c: &mut Connection, sink: &mut Sink, packet: &[u8]) -> Result<Available> {
+    let ip = Ipv4HeaderSlice::from_slice(packet).unwrap();
+    let tcp = TcpHeaderSlice::from_slice(&packet[ip.slice().len()..]).unwrap();
+
+    let data = &packet[ip.slice().len() + tcp.slice().len()..];
+
+    c.on_packet(sink, tcp, data)
+}
+
+fn config(clock: &ManualClock) -> Config {
+    Config {
+        clock: Arc::new(clock.clone()),
+        iss: Arc::new(FixedIss(ISS)),
+        ..Default::default()
+    }
+}
+
+/// A passive open that has answered the peer's SYN, which carried `options`.
+fn syn_rcvd(clock: &ManualClock, options: &[TcpOptionElement]) -> (Connection, Sink) {
+    syn_rcvd_with(config(clock), options)
+}
+
+/// Like `syn_rcvd`, for a listener set up with `config`.
+fn syn_rcvd_with(config: Config, options: &[TcpOptionElement]) -> (Connection, Sink) {
+    let mut sink = Sink::default();
+
+    let syn = packet(PEER_ISS, None, |h| h.syn = true, options, &[]);
+
+    let ip = Ipv4HeaderSlice::from_slice(&syn).unwrap();
+    let tcp = TcpHeaderSlice::from_slice(&syn[ip.slice().len()..]).unwrap();
+
+    let c = Connection::accept(&mut sink, ip, tcp, config)
+        .unwrap()
+        .unwrap();
+
+    let sent = sink.take();
+
+    assert!(sent[0].0.syn && sent[0].0.ack);
+
+    (c, sink)
+}
+
+/// A passive open that has completed the handshake, without any options.
+fn established(clock: &ManualClock) -> (Connection, Sink) {
+    let (mut c, mut sink) = syn_rcvd(clock, &[]);
+
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 1, Some(ISS + 1), |_| {}, &[], &[]),
+    )
+    .unwrap();
+
+    assert!(matches!(c.state, State::Estab));
+
+    (c, sink)
+}
+
+#[test]
+fn data_is_acknowledged_through_the_sink() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = established(&clock);
+
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 1, Some(ISS + 1), |_| {}, &[], b"hello"),
+    )
+    .unwrap();
+
+    clock.advance(c.config.ack_delay);
+    c.on_tick(&mut sink).unwrap();
+
+    let sent = sink.take();
+
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].0.acknowledgment_number, PEER_ISS + 6);
+    assert_eq!(sent[0].1, 0);
+}
+
+#[test]
+fn inexact_rst_in_syn_rcvd_gets_a_challenge_ack() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = syn_rcvd(&clock, &[]);
+
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 5, None, |h| h.rst = true, &[], &[]),
+    )
+    .unwrap();
+
+    let sent = sink.take();
+
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].0.ack && !sent[0].0.rst);
+    assert_eq!(sent[0].1, 0);
+    assert!(matches!(c.state, State::SynRcvd));
+}
+
+#[test]
+fn syn_in_syn_rcvd_window_gets_a_challenge_ack() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = syn_rcvd(&clock, &[]);
+
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 5, None, |h| h.syn = true, &[], &[]),
+    )
+    .unwrap();
+
+    let sent = sink.take();
+
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].0.ack && !sent[0].0.syn);
+}
+
+#[test]
+fn paws_rejection_in_syn_rcvd_is_acknowledged() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = syn_rcvd(&clock, &[TcpOptionElement::Timestamp(1000, 0)]);
+
+    // an older timestamp than the SYN had
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(
+            PEER_ISS + 1,
+            Some(ISS + 1),
+            |_| {},
+            &[TcpOptionElement::Timestamp(5, 0)],
+            &[],
+        ),
+    )
+    .unwrap();
+
+    let sent = sink.take();
+
+    assert_eq!(sent.len(), 1);
+    assert!(matches!(c.state, State::SynRcvd));
+}
+
+#[test]
+fn segments_after_a_reset_are_answered_with_a_rst() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = established(&clock);
+
+    c.unacked.extend(b"in flight");
+    c.on_tick(&mut sink).unwrap();
+
+    assert_eq!(sink.take()[0].1, 9);
+
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 1, None, |h| h.rst = true, &[], &[]),
+    )
+    .unwrap();
+
+    assert!(c.is_closed());
+    assert_eq!(c.error, Some(io::ErrorKind::ConnectionReset));
+
+    // a segment that was still on its way
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 1, Some(ISS + 1), |h| h.fin = true, &[], b"late"),
+    )
+    .unwrap();
+
+    let sent = sink.take();
+
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].0.rst);
+}
+
+#[test]
+fn orphaned_fin_wait_2_times_out() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = established(&clock);
+
+    c.close().unwrap();
+    c.on_tick(&mut sink).unwrap();
+
+    assert!(sink.take()[0].0.fin);
+
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 1, Some(ISS + 2), |_| {}, &[], &[]),
+    )
+    .unwrap();
+
+    assert!(matches!(c.state, State::FinWait2));
+
+    c.detached = true;
+
+    clock.advance(2 * c.config.msl);
+    c.on_tick(&mut sink).unwrap();
+
+    assert!(c.is_reapable());
+}
+
+#[test]
+fn a_reset_cancels_the_delayed_ack() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = established(&clock);
+
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 1, Some(ISS + 1), |_| {}, &[], b"data"),
+    )
+    .unwrap();
+
+    // held back for now
+    assert!(sink.take().is_empty());
+
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 5, None, |h| h.rst = true, &[], &[]),
+    )
+    .unwrap();
+
+    clock.advance(time::Duration::from_secs(1));
+
+    c.on_tick(&mut sink).unwrap();
+
+    assert!(c.is_closed());
+    assert!(sink.take().is_empty());
+}
+
+#[test]
+fn a_one_byte_buffer_does_not_announce_its_window_every_tick() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = syn_rcvd_with(
+        Config {
+            recv_buffer: 1,
+            ..config(&clock)
+        },
+        &[],
+    );
+
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 1, Some(ISS + 1), |_| {}, &[], &[]),
+    )
+    .unwrap();
+
+    for _ in 0..10 {
+        clock.advance(c.config.tick);
+        c.on_tick(&mut sink).unwrap();
+    }
+
+    assert!(sink.take().is_empty());
+}
+
+#[test]
+fn tiny_peer_mss_with_timestamps_is_raised() {
+    let clock = ManualClock::new();
+    let (c, _) = syn_rcvd(
+        &clock,
+        &[
+            TcpOptionElement::MaximumSegmentSize(4),
+            TcpOptionElement::Timestamp(1000, 0),
+        ],
+    );
+
+    assert_eq!(c.mss, MIN_MSS as u32 - TIMESTAMPS_LEN);
+}
+
+#[test]
+fn zero_peer_mss_still_lets_data_through() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = syn_rcvd(&clock, &[TcpOptionElement::MaximumSegmentSize(0)]);
+
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 1, Some(ISS + 1), |_| {}, &[], &[]),
+    )
+    .unwrap();
+
+    c.unacked.extend([7; 200]);
+    c.on_tick(&mut sink).unwrap();
+
+    let sent = sink.take();
+
+    assert_eq!(sent[0].1, MIN_MSS);
+}
+
+#[test]
+fn zero_window_is_probed_with_backoff() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = established(&clock);
+
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 1, Some(ISS + 1), |h| h.window_size = 0, &[], &[]),
+    )
+    .unwrap();
+
+    c.unacked.extend(b"blocked");
+
+    // starts the persist timer
+    c.on_tick(&mut sink).unwrap();
+
+    assert!(sink.take().is_empty());
+
+    let step = time::Duration::from_millis(10);
+    let mut probes = Vec::new();
+
+    for i in 1..=2000 {
+        clock.advance(step);
+        c.on_tick(&mut sink).unwrap();
+
+        for (h, len) in sink.take() {
+            // nothing but probes, which lie just below SND.UNA
+            assert_eq!((h.sequence_number, len), (ISS, 0));
+
+            probes.push(step * i);
+        }
+    }
+
+    // starting at the RTO, doubling each time
+    assert_eq!(
+        probes,
+        [1, 3, 7, 15].map(time::Duration::from_secs).to_vec()
+    );
+
+    // the peer's window opens
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 1, Some(ISS + 1), |_| {}, &[], &[]),
+    )
+    .unwrap();
+
+    c.on_tick(&mut sink).unwrap();
+
+    let sent = sink.take();
+
+    assert_eq!(sent.len(), 1);
+    assert_eq!((sent[0].0.sequence_number, sent[0].1), (ISS + 1, 7));
+}
+
+/// Ticks `c` every 100ms for `secs` seconds, and returns what it sent along with when.
+fn run_for(
+    c: &mut Connection,
+    sink: &mut Sink,
+    clock: &ManualClock,
+    secs: u32,
+) -> Vec<(time::Duration, TcpHeader, usize)> {
+    let step = time::Duration::from_millis(100);
+    let mut sent = Vec::new();
+
+    for i in 1..=secs * 10 {
+        clock.advance(step);
+        c.on_tick(sink).unwrap();
+
+        sent.extend(sink.take().into_iter().map(|(h, len)| (step * i, h, len)));
+    }
+
+    sent
+}
+
+#[test]
+fn unanswered_keepalive_probes_abort_the_connection() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = established(&clock);
+
+    c.set_keepalive(Some(time::Duration::from_secs(10)));
+    c.set_keepalive_interval(time::Duration::from_secs(1));
+    c.set_keepalive_count(3);
+
+    let sent = run_for(&mut c, &mut sink, &clock, 20);
+
+    let probes: Vec<_> = sent
+        .iter()
+        .filter(|(_, h, len)| !h.rst && h.sequence_number == ISS && *len == 0)
+        .map(|(at, ..)| at.as_secs())
+        .collect();
+
+    assert_eq!(probes, [10, 11, 12]);
+
+    // then the peer gets a reset, and the application an error
+    let (at, rst, _) = &sent[3];
+
+    assert_eq!(sent.len(), 4);
+    assert_eq!(at.as_secs(), 13);
+    assert!(rst.rst);
+    assert!(c.is_closed());
+    assert_eq!(c.error, Some(io::ErrorKind::TimedOut));
+}
+
+#[test]
+fn an_answered_keepalive_probe_keeps_the_connection() {
+    let clock = ManualClock::new();
+    let (mut c, mut sink) = established(&clock);
+
+    c.set_keepalive(Some(time::Duration::from_secs(10)));
+    c.set_keepalive_interval(time::Duration::from_secs(1));
+    c.set_keepalive_count(3);
+
+    assert_eq!(run_for(&mut c, &mut sink, &clock, 11).len(), 2);
+
+    // the peer acknowledges the second probe
+    deliver(
+        &mut c,
+        &mut sink,
+        &packet(PEER_ISS + 1, Some(ISS + 1), |_| {}, &[], &[]),
+    )
+    .unwrap();
+
+    // so the connection is idle again from here, and probing starts over
+    let sent = run_for(&mut c, &mut sink, &clock, 11);
+
+    let at: Vec<_> = sent.iter().map(|(at, ..)| at.as_secs()).collect();
+
+    assert_eq!(at, [10, 11]);
+    assert!(sent.iter().all(|(_, h, _)| !h.rst));
+    assert!(!c.is_closed());
+}
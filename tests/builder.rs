@@ -0,0 +1,198 @@
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown};
+use std::thread;
+use std::time::Duration;
+
+use etherparse::{Ipv4Header, Ipv4HeaderSlice, TcpHeader, TcpHeaderSlice, TcpOptionElement};
+use trust::{ChannelDevice, Device, Interface, InterfaceBuilder};
+
+const NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+
+#[test]
+fn echo_between_configured_interfaces() {
+    let (a, b) = ChannelDevice::pair().unwrap();
+
+    let mut server = InterfaceBuilder::new()
+        .address(Ipv4Addr::new(10, 1, 0, 1), NETMASK)
+        .mtu(1000)
+        .recv_buffer_size(4096)
+        .build_with_device(a)
+        .unwrap();
+
+    let mut client = InterfaceBuilder::new()
+        .address(Ipv4Addr::new(10, 1, 0, 2), NETMASK)
+        .send_buffer_size(8192)
+        .tick(Duration::from_millis(5))
+        .build_with_device(b)
+        .unwrap();
+
+    let mut listener = server.bind(7).unwrap();
+
+    let echo = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+
+        let mut received = Vec::new();
+
+        stream.read_to_end(&mut received).unwrap();
+        stream.write_all(&received).unwrap();
+        stream.flush().unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+    });
+
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+
+    let mut stream = client.connect((Ipv4Addr::new(10, 1, 0, 1), 7)).unwrap();
+
+    stream.write_all(&data).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    let mut echoed = Vec::new();
+
+    stream.read_to_end(&mut echoed).unwrap();
+
+    echo.join().unwrap();
+
+    assert_eq!(echoed, data);
+}
+
+#[test]
+fn syn_follows_the_configured_address_ttl_and_mtu() {
+    let (ours, mut peer) = ChannelDevice::pair().unwrap();
+
+    let mut interface = InterfaceBuilder::new()
+        .address(Ipv4Addr::new(10, 1, 0, 2), NETMASK)
+        .ttl(9)
+        .mtu(1000)
+        .build_with_device(ours)
+        .unwrap();
+
+    let connect = thread::spawn(move || {
+        interface
+            .connect((Ipv4Addr::new(10, 1, 0, 1), 7))
+            .err()
+            .unwrap()
+    });
+
+    let mut buf = [0; 1500];
+
+    let n = loop {
+        match peer.recv(&mut buf).unwrap() {
+            0 => thread::sleep(Duration::from_millis(1)),
+            n => break n,
+        }
+    };
+
+    let ip_h = Ipv4HeaderSlice::from_slice(&buf[..n]).unwrap();
+    let tcp_h = TcpHeaderSlice::from_slice(&buf[ip_h.slice().len()..n]).unwrap();
+
+    assert_eq!(ip_h.source_addr(), Ipv4Addr::new(10, 1, 0, 2));
+    assert_eq!(ip_h.ttl(), 9);
+    assert!(tcp_h.syn());
+    assert!(tcp_h
+        .options_iterator()
+        .any(|o| matches!(o, Ok(TcpOptionElement::MaximumSegmentSize(960)))));
+
+    // turn the connection down
+    let mut rst = TcpHeader::new(7, tcp_h.source_port(), 0, 0);
+
+    rst.rst = true;
+    rst.ack = true;
+    rst.acknowledgment_number = tcp_h.sequence_number().wrapping_add(1);
+
+    let ip = Ipv4Header::new(
+        rst.header_len_u16(),
+        64,
+        etherparse::IpNumber::TCP,
+        ip_h.destination(),
+        ip_h.source(),
+    )
+    .unwrap();
+
+    rst.checksum = rst.calc_checksum_ipv4(&ip, &[]).unwrap();
+
+    let mut packet = Vec::new();
+
+    ip.write(&mut packet).unwrap();
+    rst.write(&mut packet).unwrap();
+
+    peer.send(&packet).unwrap();
+
+    assert_eq!(
+        connect.join().unwrap().kind(),
+        io::ErrorKind::ConnectionRefused
+    );
+}
+
+#[test]
+fn destinations_off_the_subnet_are_unreachable() {
+    let (ours, _peer) = ChannelDevice::pair().unwrap();
+
+    let mut interface = InterfaceBuilder::new()
+        .address(Ipv4Addr::new(10, 1, 0, 2), NETMASK)
+        .build_with_device(ours)
+        .unwrap();
+
+    let err = interface
+        .connect((Ipv4Addr::new(10, 2, 0, 1), 7))
+        .err()
+        .unwrap();
+
+    assert_eq!(err.kind(), io::ErrorKind::NetworkUnreachable);
+}
+
+#[test]
+fn conflicting_settings_are_rejected() {
+    let addr = Ipv4Addr::new(10, 1, 0, 2);
+
+    let builders = [
+        InterfaceBuilder::new().address(addr, Ipv4Addr::new(255, 0, 255, 0)),
+        InterfaceBuilder::new().address(Ipv4Addr::new(10, 1, 0, 0), NETMASK),
+        InterfaceBuilder::new().address(Ipv4Addr::new(10, 1, 0, 255), NETMASK),
+        InterfaceBuilder::new().mtu(500),
+        InterfaceBuilder::new().mtu(2000),
+        InterfaceBuilder::new().recv_buffer_size(0),
+        InterfaceBuilder::new().send_buffer_size(0),
+        InterfaceBuilder::new().recv_buffer_size(usize::MAX),
+        InterfaceBuilder::new().msl(Duration::ZERO),
+        InterfaceBuilder::new().ack_delay(Duration::from_millis(500)),
+        InterfaceBuilder::new().ttl(0),
+        InterfaceBuilder::new().tick(Duration::ZERO),
+        InterfaceBuilder::new().tick(Duration::from_secs(5)),
+        InterfaceBuilder::new().device_name("tun1"),
+    ];
+
+    for builder in builders {
+        let (ours, _peer) = ChannelDevice::pair().unwrap();
+
+        let err = builder.build_with_device(ours).err().unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{err}");
+    }
+}
+
+#[test]
+fn setters_check_like_the_builder() {
+    let (ours, _peer) = ChannelDevice::pair().unwrap();
+
+    let mut interface = Interface::with_device(ours).unwrap();
+
+    let errs = [
+        interface.set_recv_buffer_size(0).err().unwrap(),
+        interface.set_recv_buffer_size(usize::MAX).err().unwrap(),
+        interface.set_send_buffer_size(0).err().unwrap(),
+        interface.set_msl(Duration::ZERO).err().unwrap(),
+        interface
+            .set_ack_delay(Duration::from_secs(1))
+            .err()
+            .unwrap(),
+    ];
+
+    for err in errs {
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{err}");
+    }
+
+    interface.set_recv_buffer_size(1).unwrap();
+    interface.set_send_buffer_size(1).unwrap();
+    interface.set_msl(Duration::from_millis(1)).unwrap();
+    interface.set_ack_delay(Duration::ZERO).unwrap();
+}
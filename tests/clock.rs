@@ -0,0 +1,323 @@
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use etherparse::{Ipv4Header, Ipv4HeaderSlice, TcpHeader, TcpHeaderSlice};
+use trust::{ChannelDevice, Device, Interface, ManualClock, TcpStream};
+
+#[test]
+fn syn_retransmissions_follow_a_manual_clock() {
+    let (ours, mut peer) = ChannelDevice::pair().unwrap();
+
+    let clock = ManualClock::new();
+
+    let mut interface = Interface::with_device(ours).unwrap();
+
+    interface.set_clock(clock.clone());
+
+    // nobody answers, so connect() only fails once the clock has gone through every backoff
+    let ticker = thread::spawn(move || {
+        for _ in 0..300 {
+            clock.advance(Duration::from_secs(1));
+            thread::sleep(Duration::from_millis(2));
+        }
+    });
+
+    let started = Instant::now();
+
+    let err = interface
+        .connect((Ipv4Addr::new(192, 168, 0, 1), 9000))
+        .err()
+        .unwrap();
+
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    // the backoffs add up to over two minutes of clock time
+    assert!(started.elapsed() < Duration::from_secs(30));
+
+    ticker.join().unwrap();
+
+    let mut buf = [0; 1500];
+    let mut syns = 0;
+
+    while peer.recv(&mut buf).unwrap() > 0 {
+        syns += 1;
+    }
+
+    // the first SYN and its 6 retransmissions
+    assert_eq!(syns, 7);
+}
+
+#[test]
+fn timers_run_while_packets_keep_coming_in() {
+    let (ours, mut peer) = ChannelDevice::pair().unwrap();
+
+    let mut interface = Interface::with_device(ours).unwrap();
+
+    let started = Instant::now();
+
+    // the SYN only goes out on a tick, which has to happen while the interface is already
+    // busy; nobody ever answers it
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        interface.connect((Ipv4Addr::new(192, 168, 0, 1), 9000))
+    });
+
+    let mut buf = [0; 1500];
+
+    while peer.recv(&mut buf).unwrap() == 0 {
+        assert!(
+            started.elapsed() < Duration::from_millis(300),
+            "no SYN while the interface was busy dropping junk"
+        );
+
+        // junk the interface has to look at and drop, faster than it ticks
+        for _ in 0..100 {
+            peer.send(&[0; 20]).unwrap();
+        }
+    }
+}
+
+/// The other end of a connection, driven by hand.
+struct Peer {
+    device: ChannelDevice,
+    /// The port the interface connected from.
+    port: u16,
+    /// The next sequence number the peer sends with.
+    seq: u32,
+    /// What the peer acknowledges.
+    ack: u32,
+}
+
+const PEER_ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 1);
+const PEER_PORT: u16 = 9000;
+
+impl Peer {
+    /// Everything the interface has sent that is waiting to be read.
+    fn recv(&mut self) -> Vec<(TcpHeader, Vec<u8>)> {
+        let mut buf = [0; 1500];
+        let mut segments = Vec::new();
+
+        loop {
+            let n = self.device.recv(&mut buf).unwrap();
+
+            if n == 0 {
+                return segments;
+            }
+
+            let ip = Ipv4HeaderSlice::from_slice(&buf[..n]).unwrap();
+            let tcp = TcpHeaderSlice::from_slice(&buf[ip.slice().len()..n]).unwrap();
+
+            let data = &buf[ip.slice().len() + tcp.slice().len()..n];
+
+            segments.push((tcp.to_header(), data.to_vec()));
+        }
+    }
+
+    /// Waits for the next segment from the interface.
+    fn expect(&mut self) -> (TcpHeader, Vec<u8>) {
+        let started = Instant::now();
+
+        loop {
+            if let Some(segment) = self.recv().into_iter().next() {
+                return segment;
+            }
+
+            assert!(started.elapsed() < Duration::from_secs(5), "nothing sent");
+
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn send(&mut self, flags: impl FnOnce(&mut TcpHeader), data: &[u8]) {
+        let mut tcp = TcpHeader::new(PEER_PORT, self.port, self.seq, 64240);
+
+        tcp.ack = true;
+        tcp.acknowledgment_number = self.ack;
+
+        flags(&mut tcp);
+
+        let ip = Ipv4Header::new(
+            (tcp.header_len() + data.len()) as u16,
+            64,
+            etherparse::IpNumber::TCP,
+            PEER_ADDR.octets(),
+            Ipv4Addr::new(192, 168, 0, 2).octets(),
+        )
+        .unwrap();
+
+        tcp.checksum = tcp.calc_checksum_ipv4(&ip, data).unwrap();
+
+        let mut packet = Vec::new();
+
+        ip.write(&mut packet).unwrap();
+        tcp.write(&mut packet).unwrap();
+        packet.extend_from_slice(data);
+
+        self.device.send(&packet).unwrap();
+
+        self.seq = self.seq.wrapping_add(data.len() as u32);
+    }
+}
+
+/// Moves `clock` on by `by` and gives the interface a moment to tick.
+fn advance(clock: &ManualClock, by: Duration) {
+    clock.advance(by);
+    thread::sleep(Duration::from_millis(10));
+}
+
+/// Connects an interface running off `clock` to a peer driven by hand. The clock has
+/// only moved by a few ticks by the time this returns.
+fn establish(clock: &ManualClock) -> (Interface, TcpStream, Peer) {
+    let (ours, device) = ChannelDevice::pair().unwrap();
+
+    let mut interface = Interface::with_device(ours).unwrap();
+
+    interface.set_clock(clock.clone());
+
+    let connect = thread::spawn(move || {
+        let stream = interface.connect((PEER_ADDR, PEER_PORT)).unwrap();
+
+        (interface, stream)
+    });
+
+    let mut peer = Peer {
+        device,
+        port: 0,
+        seq: 7000,
+        ack: 0,
+    };
+
+    // the SYN goes out on the next tick
+    let syn = loop {
+        advance(clock, Duration::from_millis(1));
+
+        if let Some((syn, _)) = peer.recv().into_iter().next() {
+            break syn;
+        }
+    };
+
+    assert!(syn.syn);
+
+    peer.port = syn.source_port;
+    peer.ack = syn.sequence_number.wrapping_add(1);
+
+    peer.send(|h| h.syn = true, &[]);
+    peer.seq += 1;
+
+    let (interface, stream) = connect.join().unwrap();
+
+    // the ACK of our SYN
+    assert_eq!(peer.expect().0.acknowledgment_number, peer.seq);
+
+    (interface, stream, peer)
+}
+
+#[test]
+fn time_wait_lasts_twice_the_msl() {
+    let clock = ManualClock::new();
+    let (_interface, stream, mut peer) = establish(&clock);
+
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    let fin = loop {
+        advance(&clock, Duration::from_millis(1));
+
+        if let Some((fin, _)) = peer.recv().into_iter().next() {
+            break fin;
+        }
+    };
+
+    assert!(fin.fin);
+
+    // acknowledge the FIN and send ours, which puts the interface in TIME-WAIT
+    peer.ack += 1;
+    peer.send(|h| h.fin = true, &[]);
+
+    let (ack, _) = peer.expect();
+
+    assert_eq!(ack.acknowledgment_number, peer.seq + 1);
+
+    let msl = Duration::from_secs(30);
+
+    // our FIN again, as if that ACK got lost: still acknowledged, and the wait starts over
+    for _ in 0..2 {
+        advance(&clock, 2 * msl - Duration::from_secs(1));
+
+        peer.send(|h| h.fin = true, &[]);
+
+        let (ack, _) = peer.expect();
+
+        assert!(!ack.rst);
+        assert_eq!(ack.acknowledgment_number, peer.seq + 1);
+    }
+
+    advance(&clock, 2 * msl);
+
+    // by now the connection is gone
+    peer.send(|h| h.fin = true, &[]);
+
+    assert!(peer.expect().0.rst);
+}
+
+#[test]
+fn unanswered_keepalives_abort_the_connection() {
+    let clock = ManualClock::new();
+    let (_interface, mut stream, mut peer) = establish(&clock);
+
+    stream.set_keepalive(Some(Duration::from_secs(60))).unwrap();
+    stream
+        .set_keepalive_interval(Duration::from_secs(10))
+        .unwrap();
+    stream.set_keepalive_count(3).unwrap();
+
+    let mut probes = Vec::new();
+
+    for secs in 1..=100 {
+        advance(&clock, Duration::from_secs(1));
+
+        for (h, data) in peer.recv() {
+            assert!(data.is_empty());
+
+            probes.push((secs, h.rst));
+        }
+    }
+
+    // three probes after the connection has been idle for a minute, 10s apart, and a RST
+    // when the last one goes unanswered
+    assert_eq!(probes, [(60, false), (70, false), (80, false), (90, true)]);
+
+    let err = stream.read(&mut [0; 16]).unwrap_err();
+
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn retransmissions_back_off_until_the_connection_times_out() {
+    let clock = ManualClock::new();
+    let (_interface, mut stream, mut peer) = establish(&clock);
+
+    stream.write_all(b"lost").unwrap();
+
+    let mut sent = Vec::new();
+
+    for secs in 0..500 {
+        advance(&clock, Duration::from_secs(1));
+
+        for (_, data) in peer.recv() {
+            assert_eq!(data, b"lost");
+
+            sent.push(secs);
+        }
+    }
+
+    let gaps: Vec<_> = sent.windows(2).map(|w| w[1] - w[0]).collect();
+
+    // the RTO doubles from 1s with each retransmission, up to the 60s maximum
+    assert_eq!(gaps, [1, 2, 4, 8, 16, 32, 60, 60, 60, 60, 60, 60]);
+
+    let err = stream.read(&mut [0; 16]).unwrap_err();
+
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
@@ -0,0 +1,171 @@
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Shutdown};
+use std::thread;
+use std::time::Duration;
+
+use trust::{Device, Interface, LinkConditions, LinkStats, SimulatedLink};
+
+const MB: usize = 1024 * 1024;
+
+/// Bytes that don't repeat for a long while, so data delivered at the wrong offset shows.
+fn pattern(len: usize) -> Vec<u8> {
+    let mut x: u32 = 0x1234_5678;
+
+    (0..len)
+        .map(|_| {
+            x = x.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (x >> 24) as u8
+        })
+        .collect()
+}
+
+/// Pushes `len` bytes from one interface to another across a link under `conditions`,
+/// checks that every byte arrived as sent, and returns what the link did along the way.
+fn transfer(conditions: LinkConditions, len: usize) -> LinkStats {
+    let (link, a, b) = SimulatedLink::new(conditions).unwrap();
+
+    let mut server = Interface::with_device(a).unwrap();
+    let mut client = Interface::with_device(b).unwrap();
+
+    client.set_send_buffer_size(256 * 1024).unwrap();
+
+    let mut listener = server.bind(9000).unwrap();
+
+    let receiver = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+
+        let mut received = Vec::new();
+
+        stream.read_to_end(&mut received).unwrap();
+
+        received
+    });
+
+    let data = pattern(len);
+
+    let mut stream = client
+        .connect((Ipv4Addr::new(192, 168, 0, 2), 9000))
+        .unwrap();
+
+    stream.write_all(&data).unwrap();
+    stream.flush().unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    let received = receiver.join().unwrap();
+
+    assert_eq!(received.len(), data.len());
+    assert!(received == data, "received data differs from what was sent");
+
+    link.stats()
+}
+
+#[test]
+fn delayed_rate_limited_link() {
+    let stats = transfer(
+        LinkConditions {
+            delay: Duration::from_millis(5),
+            rate: Some(20 * MB as u64),
+            ..Default::default()
+        },
+        4 * MB,
+    );
+
+    assert_eq!(stats.lost, 0);
+    assert_eq!(stats.dropped, 0);
+}
+
+#[test]
+fn lossy_link() {
+    let stats = transfer(
+        LinkConditions {
+            loss: 0.02,
+            delay: Duration::from_millis(2),
+            seed: 1,
+            ..Default::default()
+        },
+        2 * MB,
+    );
+
+    assert!(stats.lost > 0);
+}
+
+#[test]
+fn duplicating_link() {
+    let stats = transfer(
+        LinkConditions {
+            duplicate: 0.05,
+            delay: Duration::from_millis(2),
+            seed: 2,
+            ..Default::default()
+        },
+        2 * MB,
+    );
+
+    assert!(stats.duplicated > 0);
+}
+
+#[test]
+fn reordering_link() {
+    let stats = transfer(
+        LinkConditions {
+            reorder: 0.05,
+            reorder_delay: Duration::from_millis(5),
+            delay: Duration::from_millis(2),
+            seed: 3,
+            ..Default::default()
+        },
+        2 * MB,
+    );
+
+    assert!(stats.reordered > 0);
+}
+
+#[test]
+fn corrupting_link() {
+    let stats = transfer(
+        LinkConditions {
+            corrupt: 0.02,
+            delay: Duration::from_millis(2),
+            seed: 4,
+            ..Default::default()
+        },
+        2 * MB,
+    );
+
+    assert!(stats.corrupted > 0);
+}
+
+#[test]
+fn everything_at_once() {
+    transfer(
+        LinkConditions {
+            loss: 0.01,
+            duplicate: 0.01,
+            reorder: 0.02,
+            reorder_delay: Duration::from_millis(5),
+            corrupt: 0.01,
+            delay: Duration::from_millis(3),
+            rate: Some(10 * MB as u64),
+            seed: 5,
+        },
+        2 * MB,
+    );
+}
+
+#[test]
+fn packets_nobody_reads_are_dropped() {
+    let (link, mut a, _b) = SimulatedLink::new(LinkConditions::default()).unwrap();
+
+    // far more than the other end's queue holds
+    for _ in 0..1000 {
+        a.send(&[0; 100]).unwrap();
+        thread::sleep(Duration::from_micros(10));
+    }
+
+    thread::sleep(Duration::from_millis(100));
+
+    let stats = link.stats();
+
+    assert!(stats.dropped > 0);
+    assert!(stats.delivered < 1000);
+}
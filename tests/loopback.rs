@@ -0,0 +1,145 @@
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use trust::{ChannelDevice, Interface};
+
+#[test]
+fn two_interfaces_over_a_channel() {
+    let (a, b) = ChannelDevice::pair().unwrap();
+
+    let mut server = Interface::with_device(a).unwrap();
+    let mut client = Interface::with_device(b).unwrap();
+
+    let mut listener = server.bind(9000).unwrap();
+
+    // echoes everything back until the client shuts down its side
+    let echo = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+
+        let mut received = Vec::new();
+
+        stream.read_to_end(&mut received).unwrap();
+        stream.write_all(&received).unwrap();
+        stream.flush().unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+    });
+
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+
+    let mut stream = client
+        .connect((Ipv4Addr::new(192, 168, 0, 2), 9000))
+        .unwrap();
+
+    stream.write_all(&data).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    let mut echoed = Vec::new();
+
+    stream.read_to_end(&mut echoed).unwrap();
+
+    echo.join().unwrap();
+
+    assert_eq!(echoed, data);
+}
+
+#[test]
+fn flush_sends_what_the_cork_holds_back() {
+    let (a, b) = ChannelDevice::pair().unwrap();
+
+    let mut server = Interface::with_device(a).unwrap();
+    let mut client = Interface::with_device(b).unwrap();
+
+    let mut listener = server.bind(9000).unwrap();
+
+    let mut stream = client
+        .connect((Ipv4Addr::new(192, 168, 0, 2), 9000))
+        .unwrap();
+
+    let mut accepted = listener.accept().unwrap();
+
+    stream.set_cork(true).unwrap();
+    stream
+        .set_write_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    stream.write_all(b"corked").unwrap();
+    stream.flush().unwrap();
+
+    let mut buf = [0; 6];
+
+    accepted.read_exact(&mut buf).unwrap();
+
+    assert_eq!(&buf, b"corked");
+}
+
+#[test]
+fn accepted_streams_take_the_listeners_write_timeout() {
+    let (a, b) = ChannelDevice::pair().unwrap();
+
+    let mut server = Interface::with_device(a).unwrap();
+    let mut client = Interface::with_device(b).unwrap();
+
+    let mut listener = server.bind(9000).unwrap();
+
+    let timeout = Some(Duration::from_secs(3));
+
+    listener.set_write_timeout(timeout).unwrap();
+
+    assert_eq!(listener.write_timeout().unwrap(), timeout);
+    assert!(listener.set_write_timeout(Some(Duration::ZERO)).is_err());
+
+    let _stream = client
+        .connect((Ipv4Addr::new(192, 168, 0, 2), 9000))
+        .unwrap();
+
+    let accepted = listener.accept().unwrap();
+
+    assert_eq!(accepted.write_timeout().unwrap(), timeout);
+    assert_eq!(accepted.read_timeout().unwrap(), None);
+}
+
+#[test]
+fn connections_time_out_once_the_other_end_is_gone() {
+    let (a, b) = ChannelDevice::pair().unwrap();
+
+    let mut server = Interface::with_device(a).unwrap();
+    let mut client = Interface::with_device(b).unwrap();
+
+    let mut listener = server.bind(9000).unwrap();
+
+    let stream = client
+        .connect((Ipv4Addr::new(192, 168, 0, 2), 9000))
+        .unwrap();
+
+    let mut accepted = listener.accept().unwrap();
+
+    accepted
+        .set_keepalive(Some(Duration::from_millis(100)))
+        .unwrap();
+    accepted
+        .set_keepalive_interval(Duration::from_millis(100))
+        .unwrap();
+    accepted.set_keepalive_count(3).unwrap();
+    accepted
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    // pulls the plug
+    drop(client);
+    drop(stream);
+
+    let started = Instant::now();
+
+    let err = accepted.read(&mut [0; 16]).unwrap_err();
+
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    // the keepalive gave up, not the read timeout
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    drop(accepted);
+    drop(listener);
+    drop(server);
+}